@@ -15,638 +15,1671 @@
 
 use actix_web::web::Json;
 
-use bson::{doc, Document};
-use chrono::Utc;
-use futures::{StreamExt, TryStreamExt};
-use jsonwebtoken::{encode, EncodingKey, Header};
-use mongodb::{
-    options::{FindOneOptions, FindOptions, UpdateOptions},
-    Collection, Database,
+use argon2::{
+    password_hash::{
+        rand_core::{OsRng, RngCore},
+        PasswordHash, PasswordHasher, PasswordVerifier, SaltString,
+    },
+    Argon2,
 };
+use bson::{doc, Bson, Document};
+use chrono::Utc;
+use jsonwebtoken::{decode, encode, DecodingKey, EncodingKey, Header, Validation};
 use serde_json::json;
 use sha3::{Digest, Sha3_256};
+use std::collections::HashMap;
 use std::env;
+use std::sync::Arc;
 
+use crate::errors::{CoreError, CoreResult};
+use crate::mail::MailSender;
+use crate::pagination::{self, Page, PageRequest, DEFAULT_PAGE_SIZE, MAX_PAGE_SIZE};
+use crate::rating::{self, Aggregate, Rating};
+use crate::screening::{Rules, Screening, Submission, Verdict};
+use crate::search;
+use crate::storage::{ReviewFilter, Storage, StorageError, TagFilter};
+use crate::taxonomy::{self, Taxonomy};
 use crate::types::*;
-pub struct Core {
-    users: Collection<Document>,
-    apps: Collection<Document>,
-    apps_versions: Collection<Document>,
-    reviews: Collection<Document>,
-    personal_libraries: Collection<Document>,
+use crate::validation::Validate;
+use crate::versions;
+
+const ACCESS_TOKEN_TTL: i64 = 900; //15 minutes
+const REFRESH_TOKEN_TTL: i64 = 2592000; //30 days
+const CONFIRMATION_TTL: i64 = 86400; //day
+const CONFIRMATION_COOLDOWN: i64 = 60;
+const RESET_TTL: i64 = 3600; //hour
+const RESET_COOLDOWN: i64 = 60;
+const CHANGELOG_EXCERPT_CHARS: usize = 300;
+const MAX_SEARCH_CANDIDATES: usize = 1000;
+const DAY: i64 = 86400;
+/// Reporter of reviews held by content screening, user names can not contain `<`
+const SCREENING_REPORTER: &str = "<screening>";
+
+pub struct Core<S: Storage + ?Sized = dyn Storage> {
+    storage: Arc<S>,
+    mailer: Arc<dyn MailSender>,
+    screening: Screening,
     jwt_secret: String,
     salt: String,
+    public_url: String,
 }
 
-impl Core {
-    pub fn new(db: &Database) -> Core {
+impl<S: Storage + ?Sized> Core<S> {
+    pub fn new(storage: Arc<S>, mailer: Arc<dyn MailSender>) -> Core<S> {
         Core {
-            users: db.collection("users"),
-            apps: db.collection("apps"),
-            apps_versions: db.collection("apps_versions"),
-            reviews: db.collection("reviews"),
-            personal_libraries: db.collection("personal_libraries"),
+            storage,
+            mailer,
+            screening: Screening::new(match env::var("SCREENING_RULES") {
+                Ok(path) => Rules::from_file(&path).expect("Can not read screening rules"),
+                Err(_) => Rules::default(),
+            }),
             jwt_secret: env::var("JWT_SECRET").expect("JWT_SECRET not found"),
             salt: env::var("SALT").expect("Hash salt not found"),
+            public_url: env::var("PUBLIC_URL").unwrap_or_default(),
         }
     }
-    pub async fn get_apps(&self) -> Result<Vec<Document>, serde_json::Value> {
-        self.get_collection(&self.apps).await
+    pub async fn get_apps(&self, page: &PageQuery) -> CoreResult<Page> {
+        let page = PageRequest::new(page, apps_sort())?;
+        Ok(self.storage.get_apps(&page).await?)
     }
 
-    pub async fn get_app(&self, name: &String) -> serde_json::Value {
-        let response = self.apps.find_one(doc! {"app_id":&name}, None).await;
-        match response {
-            Ok(response) => match response {
-                Some(result) => json!({
-                    "code":"ok_body",
-                    "body": result
-                }),
-                None => {
-                    json! ({
-                        "code":"denied",
-                        "msg":"This app does not exist"
-                    })
-                }
-            },
-            Err(_) => {
-                json! ({
-                    "code":"err",
-                    "msg":"Unknown error"
-                })
-            }
+    pub async fn get_app(&self, name: &str) -> CoreResult<Document> {
+        match self.storage.find_app(name).await? {
+            Some(result) => Ok(result),
+            None => Err(app_not_found()),
         }
     }
 
-    pub async fn get_personal_library(&self, name: &String) -> serde_json::Value {
-        let response = self
-            .personal_libraries
-            .find_one(doc! {"name":&name}, None)
-            .await;
-        match response {
-            Ok(response) => match response {
-                Some(result) => json!({
-                    "code":"ok_body",
-                    "body": result.get_array("apps").unwrap()
-                }),
-                None => {
-                    json! ({
-                        "code":"denied",
-                        "msg":"This user does not exist"
-                    })
-                }
-            },
-            Err(_) => {
-                json! ({
-                    "code":"err",
-                    "msg":"Unknown error"
-                })
-            }
+    pub async fn get_personal_library(&self, name: &str) -> CoreResult<Vec<Bson>> {
+        match self.storage.find_personal_library(name).await? {
+            Some(result) => Ok(result.get_array("apps").cloned().unwrap_or_default()),
+            None => Err(CoreError::NotFound("This user does not exist".to_string())),
         }
     }
 
-    pub async fn get_rating(&self, app_id: &String) -> serde_json::Value {
-        let result = self
-            .reviews
-            .aggregate(
-                [
-                    doc! {"$match":{"app_id":app_id}},
-                    doc! {
-                        "$group": {
-                            "_id": "$app_id",
-                            "rating": {
-                                "$avg": "$score"
-                            },
-                        },
-                    },
-                ],
-                None,
-            )
-            .await;
-
-        match result {
-            Ok(mut result) => match result.next().await {
-                Some(result) => match result {
-                    Ok(result) => {
-                        json! ({
-                            "code":"ok_body",
-                            "body":result
-                        })
-                    }
-                    Err(_) => {
-                        json! ({
-                            "code":"err",
-                            "msg":"Unknown error"
-                        })
-                    }
-                },
-                None => {
-                    json! ({
-                        "code":"denied",
-                        "msg":"This app does not exist"
-                    })
-                }
-            },
-            Err(_) => {
-                json! ({
-                    "code":"err",
-                    "msg":"Unknown error"
-                })
-            }
-        }
+    /// Rating of the app, empty if nobody reviewed it yet
+    pub async fn get_rating(&self, app_id: &str) -> CoreResult<Rating> {
+        let app = self.get_app(app_id).await?;
+        let (count, sum) = self.storage.get_rating_totals().await?;
+        Ok(Rating::new(
+            app_id,
+            &Aggregate::of(&app),
+            rating::prior_mean(count, sum),
+        ))
     }
 
-    async fn create_personal_library(&self, name: &String) {
-        self.personal_libraries
-            .insert_one(
-                doc! {
-                    "name":name,
-                    "apps":[]
-                },
-                None,
+    /// Recounts the ratings of all apps from their reviews, returns the number of rated apps
+    pub async fn rebuild_ratings(&self) -> CoreResult<usize> {
+        let aggregates = rating::aggregates(&self.storage.get_score_counts().await?);
+        let rated = aggregates.len();
+        self.storage
+            .set_ratings(
+                aggregates
+                    .into_iter()
+                    .map(|(app_id, aggregate)| (app_id, aggregate.to_document()))
+                    .collect(),
             )
-            .await
-            .unwrap();
+            .await?;
+        Ok(rated)
     }
 
-    pub async fn get_reviews(&self, app_id: &String) -> Result<Vec<Document>, serde_json::Value> {
-        self.get_collection_with_params(&self.reviews, doc! {"app_id":app_id})
-            .await
+    async fn create_personal_library(&self, name: &str) -> CoreResult<()> {
+        Ok(self.storage.create_personal_library(name).await?)
     }
 
-    pub async fn get_apps_by_tag(
+    pub async fn get_reviews(
         &self,
-        info: &AppTags,
-    ) -> Result<Vec<Document>, serde_json::Value> {
-        self.get_collection_with_params(&self.apps, doc! {"tags":{"$in":&info.tags}})
-            .await
+        app_id: &str,
+        query: &ReviewsQuery,
+        page: &PageQuery,
+    ) -> CoreResult<Page> {
+        query.validate()?;
+        let page = PageRequest::new(page, reviews_sort(query.sort))?;
+        let filter = ReviewFilter {
+            app_id: app_id.to_string(),
+            min_score: query.min_score,
+            max_score: query.max_score,
+            with_text: query.with_text,
+            version: query.version.clone(),
+        };
+        Ok(self.storage.get_reviews(&filter, &page).await?)
     }
 
-    pub async fn get_versions(&self, app_id: &String) -> Result<Vec<Document>, serde_json::Value> {
-        self.get_collection_with_params_and_sort(
-            &self.apps_versions,
-            doc! {"app_id":app_id},
-            doc! {"timestamp": -1},
-        )
-        .await
+    pub async fn get_apps_by_tag(&self, info: &AppTags, page: &PageQuery) -> CoreResult<Page> {
+        if info.is_empty() {
+            return Err(CoreError::Validation("No tags to filter by".to_string()));
+        }
+        let page = PageRequest::new(page, apps_sort())?;
+        let filter = self.tag_filter(info).await?;
+        Ok(self.storage.get_apps_by_tags(&filter, &page).await?)
     }
 
-    pub async fn write_review(&self, name: &String, info: &Json<ReviewData>) -> serde_json::Value {
-        let options = FindOneOptions::builder()
-            .projection(doc! {"_id" : 1})
-            .build();
-        let response = self
-            .apps
-            .find_one(doc! {"app_id":&info.app_id}, options)
-            .await;
-
-        match response {
-            Ok(response) => match response {
-                Some(_) => {
-                    let options = UpdateOptions::builder().upsert(Some(true)).build();
-                    let response = self
-                        .reviews
-                        .update_one(
-                            doc! {"user_name": name},
-                            doc! {"$set": {
-                                "app_id":&info.app_id,
-                                "text":&info.text,
-                                "score":&info.score,
-                                "timestamp":Utc::now().timestamp()
-                            }},
-                            options,
-                        )
-                        .await;
-                    match response {
-                        Ok(_) => {
-                            json! ({
-                                "code":"ok",
-                                "msg":"The review is written"
-                            })
-                        }
-                        Err(_) => {
-                            json! ({
-                                "code":"err",
-                                "msg":"Validation error"
-                            })
-                        }
-                    }
+    /// Number of apps with each tag among the apps matching the filter
+    pub async fn get_tag_facets(&self, info: &AppTags) -> CoreResult<Vec<Document>> {
+        let filter = self.tag_filter(info).await?;
+        Ok(self.storage.get_tag_counts(&filter).await?)
+    }
+
+    pub async fn get_tags(&self) -> CoreResult<Vec<Document>> {
+        Ok(self.storage.get_tags().await?)
+    }
+
+    pub async fn create_tag(&self, tag: &TagData) -> CoreResult<serde_json::Value> {
+        tag.validate()?;
+        let document = tag_document(&self.taxonomy().await?, tag)?;
+        match self.storage.insert_tag(document).await {
+            Ok(_) => Ok(json! ({
+                "code":"ok",
+                "msg":"Tag created"
+            })),
+            Err(StorageError::Duplicate) => {
+                Err(CoreError::Conflict("Tag already exists".to_string()))
+            }
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    pub async fn edit_tag(&self, tag: &TagData) -> CoreResult<serde_json::Value> {
+        tag.validate()?;
+        let taxonomy = self.taxonomy().await?;
+        if taxonomy.find(&tag.slug).is_none() {
+            return Err(tag_not_found());
+        }
+        self.storage
+            .update_tag(&tag.slug, tag_document(&taxonomy, tag)?)
+            .await?;
+
+        Ok(json! ({
+            "code":"ok",
+            "msg":"Tag updated"
+        }))
+    }
+
+    /// Renames the tag in the catalog and in every app, the old slug stays as an alias
+    pub async fn rename_tag(&self, info: &TagChange) -> CoreResult<serde_json::Value> {
+        info.validate()?;
+        let taxonomy = self.taxonomy().await?;
+        let slug = taxonomy::slugify(&info.to);
+        if slug.is_empty() {
+            return Err(CoreError::Validation("Tag slug is empty".to_string()));
+        }
+
+        let to = match taxonomy.find(&info.from) {
+            Some(tag) => {
+                if taxonomy
+                    .owner(&slug)
+                    .is_some_and(|owner| owner != info.from)
+                {
+                    return Err(CoreError::Conflict(format!("Tag {} already exists", slug)));
                 }
-                None => {
-                    json! ({
-                        "code":"denied",
-                        "msg":"This app does not exist"
-                    })
+
+                let mut aliases: Vec<&str> = taxonomy::aliases(tag);
+                aliases.retain(|alias| *alias != slug);
+                aliases.push(&info.from);
+                self.storage
+                    .update_tag(&info.from, doc! {"slug": &slug, "aliases": aliases})
+                    .await?;
+                for child in taxonomy.children(&info.from) {
+                    self.storage
+                        .update_tag(child, doc! {"parent": &slug})
+                        .await?;
                 }
-            },
-            Err(_) => {
-                json! ({
-                    "code":"err",
-                    "msg":"Unknown error"
-                })
+                slug
             }
-        }
+            None => taxonomy.resolve(&slug),
+        };
+        self.storage.replace_app_tag(&info.from, &to).await?;
+
+        Ok(json! ({
+            "code":"ok",
+            "msg":"Tag renamed"
+        }))
     }
 
-    pub async fn update_user(&self, name: &String, info: &Json<UserData>) -> serde_json::Value {
-        let response = self
-            .users
-            .update_one(
-                doc! {"name": name},
-                doc! {"$set": {
-                    "email":&info.email,
-                    "img":&info.img
-                }},
-                None,
-            )
-            .await;
-        match response {
-            Ok(_) => {
-                json! ({
-                    "code":"ok",
-                    "msg":"User information updated"
-                })
+    /// Moves the apps, children and aliases of a tag to another one and removes it
+    pub async fn merge_tags(&self, info: &TagChange) -> CoreResult<serde_json::Value> {
+        info.validate()?;
+        let taxonomy = self.taxonomy().await?;
+        let to = taxonomy.resolve(&info.to);
+        let target = taxonomy.find(&to).ok_or_else(tag_not_found)?;
+        if info.from == to {
+            return Err(CoreError::Validation(
+                "A tag can not be merged into itself".to_string(),
+            ));
+        }
+
+        let mut aliases: Vec<String> = taxonomy::aliases(target)
+            .into_iter()
+            .map(String::from)
+            .collect();
+        let mut merged = vec![taxonomy::slugify(&info.from)];
+        if let Some(source) = taxonomy.find(&info.from) {
+            if taxonomy.with_children(&info.from).contains(&to) {
+                return Err(CoreError::Validation(
+                    "A tag can not be merged into a tag below it".to_string(),
+                ));
             }
-            Err(_) => {
-                json! ({
-                    "code":"err",
-                    "msg":"Unknown error"
-                })
+            merged.extend(taxonomy::aliases(source).into_iter().map(String::from));
+            for child in taxonomy.children(&info.from) {
+                self.storage.update_tag(child, doc! {"parent": &to}).await?;
+            }
+            self.storage.delete_tag(&info.from).await?;
+        } else if taxonomy.owner(&merged[0]).is_some() {
+            merged.clear();
+        }
+        for alias in merged {
+            if !alias.is_empty() && alias != to && !aliases.contains(&alias) {
+                aliases.push(alias);
             }
         }
+        self.storage
+            .update_tag(&to, doc! {"aliases": aliases})
+            .await?;
+        self.storage.replace_app_tag(&info.from, &to).await?;
+
+        Ok(json! ({
+            "code":"ok",
+            "msg":"Tags merged"
+        }))
     }
 
-    pub async fn get_latest_version(&self, app_id: &String) -> serde_json::Value {
-        let options = FindOptions::builder()
-            .projection(doc! {"_id" : 0})
-            .sort(doc! {"timestamp":-1})
-            .limit(1)
-            .build();
-
-        let result = self
-            .apps_versions
-            .find(doc! {"app_id":app_id}, options)
-            .await;
-
-        match result {
-            Ok(mut cursor) => match cursor.next().await {
-                Some(result) => match result {
-                    Ok(result) => {
-                        json! ({
-                            "code":"ok_body",
-                            "body":result
-                        })
-                    }
-                    Err(_) => {
-                        json! ({
-                            "code":"err",
-                            "msg":"Unknown error"
-                        })
-                    }
-                },
-                None => {
-                    json! ({
-                        "code":"denied",
-                        "msg":"Versions of this app does not exists"
-                    })
+    /// Apps ranked by relevance to the query, filtered by platform and rating
+    pub async fn search(&self, query: &SearchQuery) -> CoreResult<Vec<Document>> {
+        query.validate()?;
+        let search = search::Query::parse(&query.q);
+        if search.is_empty() {
+            return Err(CoreError::Validation(
+                "Search query must contain a word".to_string(),
+            ));
+        }
+
+        let mut apps = self.storage.search_apps(&search).await?;
+        apps.truncate(MAX_SEARCH_CANDIDATES);
+        let app_ids: Vec<String> = apps
+            .iter()
+            .map(|app| app.get_str("app_id").unwrap_or_default().to_string())
+            .collect();
+
+        if let Some(target) = target(&query.platform, &query.arch, &None) {
+            let versions = self.storage.get_versions_of_apps(&app_ids).await?;
+            apps.retain(|app| {
+                let app_id = app.get_str("app_id").unwrap_or_default();
+                versions.iter().any(|v| {
+                    v.get_str("app_id").unwrap_or_default() == app_id && target.score(v).is_some()
+                })
+            });
+        }
+
+        if let Some(min_rating) = query.min_rating {
+            apps.retain(|app| {
+                Aggregate::of(app)
+                    .average()
+                    .is_some_and(|average| average >= min_rating)
+            });
+        }
+
+        apps.truncate(
+            query
+                .limit
+                .unwrap_or(DEFAULT_PAGE_SIZE)
+                .clamp(1, MAX_PAGE_SIZE),
+        );
+        Ok(apps)
+    }
+
+    /// Every version of the app newest first, the whole list since semver order is not stored
+    pub async fn get_versions(
+        &self,
+        app_id: &str,
+        query: &VersionsQuery,
+    ) -> CoreResult<Vec<Document>> {
+        let versions = versions::sort(self.storage.get_versions(app_id).await?, query.prerelease);
+        match target(&query.platform, &query.arch, &query.package_type) {
+            Some(target) => Ok(target.filter(versions)),
+            None => Ok(versions),
+        }
+    }
+
+    pub async fn write_review(
+        &self,
+        name: &str,
+        info: &Json<ReviewData>,
+    ) -> CoreResult<serde_json::Value> {
+        info.validate()?;
+        self.require_confirmed(name, "write reviews").await?;
+        self.get_app(&info.app_id).await?;
+
+        let now = Utc::now().timestamp();
+        let reviews = self.storage.get_reviews_by_user(name).await?;
+        let revisions = self
+            .storage
+            .get_revisions_by_user(name, now - self.screening.history())
+            .await?;
+        //every write of the user counts, edits of this review too
+        let writes: Vec<Document> = reviews.iter().chain(revisions.iter()).cloned().collect();
+        let others: Vec<Document> = reviews
+            .into_iter()
+            .filter(|review| review.get_str("app_id").unwrap_or_default() != info.app_id)
+            .collect();
+        let held = match self.screening.screen(&Submission {
+            text: &info.text,
+            others: &others,
+            writes: &writes,
+            now,
+        }) {
+            Verdict::Accept => None,
+            Verdict::Hold(reason) => Some(reason),
+            Verdict::Reject(reason) => return Err(CoreError::Validation(reason)),
+        };
+
+        let mut review = doc! {
+            "text":&info.text,
+            "score":&info.score,
+            "version":&info.version,
+            "timestamp":now
+        };
+        if held.is_some() {
+            review.insert("hidden", true);
+        }
+        let previous = self
+            .storage
+            .upsert_review(name, &info.app_id, review)
+            .await?;
+        if let Some(previous) = previous {
+            self.storage
+                .insert_review_revision(review_revision(&previous, false))
+                .await?;
+        }
+
+        match held {
+            Some(reason) => {
+                let report = doc! {
+                    "app_id": &info.app_id,
+                    "author": name,
+                    "user_name": SCREENING_REPORTER,
+                    "reason": "screening",
+                    "comment": &reason,
+                    "status": "open",
+                    "timestamp": now,
+                };
+                match self.storage.insert_review_report(report).await {
+                    Err(StorageError::Duplicate) => {}
+                    result => result?,
                 }
+                Ok(json! ({
+                    "code":"ok",
+                    "msg":format!("The review is written and waits for moderation: {}", reason)
+                }))
+            }
+            None => Ok(json! ({
+                "code":"ok",
+                "msg":"The review is written"
+            })),
+        }
+    }
+
+    /// Removes the review, it stays in the review history
+    pub async fn delete_review(&self, name: &str, app_id: &str) -> CoreResult<serde_json::Value> {
+        let review = self
+            .storage
+            .delete_review(name, app_id)
+            .await?
+            .ok_or_else(|| CoreError::NotFound("Review does not exist".to_string()))?;
+        self.storage
+            .insert_review_revision(review_revision(&review, true))
+            .await?;
+
+        Ok(json! ({
+            "code":"ok",
+            "msg":"The review is deleted"
+        }))
+    }
+
+    pub async fn vote_review(
+        &self,
+        name: &str,
+        info: &ReviewVote,
+    ) -> CoreResult<serde_json::Value> {
+        info.validate()?;
+        if *name == info.user_name {
+            return Err(CoreError::Forbidden(
+                "You can not vote for your own review".to_string(),
+            ));
+        }
+        self.require_confirmed(name, "vote for reviews").await?;
+        if self
+            .storage
+            .find_review(&info.user_name, &info.app_id)
+            .await?
+            .is_none()
+        {
+            return Err(CoreError::NotFound("Review does not exist".to_string()));
+        }
+
+        let helpful = match info.vote {
+            Vote::Helpful => Some(true),
+            Vote::Unhelpful => Some(false),
+            Vote::None => None,
+        };
+        self.storage
+            .vote_review(&info.app_id, &info.user_name, name, helpful)
+            .await?;
+
+        Ok(json! ({
+            "code":"ok",
+            "msg":"Vote counted"
+        }))
+    }
+
+    /// Every review of the user with replies, hidden ones too
+    pub async fn get_user_reviews(&self, name: &str) -> CoreResult<Vec<Document>> {
+        Ok(self.storage.get_reviews_by_user(name).await?)
+    }
+
+    /// Writes or edits the public reply of the app author to a review
+    pub async fn reply_review(
+        &self,
+        name: &str,
+        role: &str,
+        info: &ReviewReply,
+    ) -> CoreResult<serde_json::Value> {
+        info.validate()?;
+        self.find_owned_app(name, role, &info.app_id).await?;
+        let review = self
+            .storage
+            .find_review(&info.user_name, &info.app_id)
+            .await?
+            .ok_or_else(|| CoreError::NotFound("Review does not exist".to_string()))?;
+
+        let now = Utc::now().timestamp();
+        let reply = match review.get_document("reply") {
+            Ok(reply) => doc! {
+                "text": &info.text,
+                "author": name,
+                "timestamp": reply.get("timestamp").cloned().unwrap_or(Bson::Int64(now)),
+                "edited": now,
             },
-            Err(_) => json! ({
-                "code":"denied",
-                "msg":"Unknown error"
-            }),
+            Err(_) => doc! {
+                "text": &info.text,
+                "author": name,
+                "timestamp": now,
+            },
+        };
+        self.storage
+            .update_review(&info.user_name, &info.app_id, doc! {"reply": reply})
+            .await?;
+
+        Ok(json! ({
+            "code":"ok",
+            "msg":"The reply is written"
+        }))
+    }
+
+    pub async fn delete_reply(
+        &self,
+        name: &str,
+        role: &str,
+        info: &ReviewAuthor,
+    ) -> CoreResult<serde_json::Value> {
+        info.validate()?;
+        self.find_owned_app(name, role, &info.app_id).await?;
+        let replied = self
+            .storage
+            .find_review(&info.user_name, &info.app_id)
+            .await?
+            .is_some_and(|review| review.get_document("reply").is_ok());
+        if !replied {
+            return Err(CoreError::NotFound("Reply does not exist".to_string()));
         }
+        self.storage
+            .update_review(&info.user_name, &info.app_id, doc! {"reply": Bson::Null})
+            .await?;
+
+        Ok(json! ({
+            "code":"ok",
+            "msg":"The reply is deleted"
+        }))
     }
 
-    pub async fn delete_app_from_personal_library(
+    pub async fn report_review(
         &self,
-        name: &String,
-        app: &String,
-    ) -> serde_json::Value {
-        let response = self
-            .personal_libraries
-            .update_one(
-                doc! {"name": name},
-                doc! {"$pull": {
-                    "apps":&app,
-                }},
-                None,
-            )
-            .await;
-        match response {
-            Ok(_) => {
-                json! ({
+        name: &str,
+        info: &ReviewReport,
+    ) -> CoreResult<serde_json::Value> {
+        info.validate()?;
+        if *name == info.user_name {
+            return Err(CoreError::Forbidden(
+                "You can not report your own review".to_string(),
+            ));
+        }
+        self.require_confirmed(name, "report reviews").await?;
+        if self
+            .storage
+            .find_review(&info.user_name, &info.app_id)
+            .await?
+            .is_none()
+        {
+            return Err(CoreError::NotFound("Review does not exist".to_string()));
+        }
+
+        let report = doc! {
+            "app_id": &info.app_id,
+            "author": &info.user_name,
+            "user_name": name,
+            "reason": bson::to_bson(&info.reason).map_err(|e| CoreError::Internal(Box::new(e)))?,
+            "comment": &info.comment,
+            "status": "open",
+            "timestamp": Utc::now().timestamp(),
+        };
+        match self.storage.insert_review_report(report).await {
+            Err(StorageError::Duplicate) => Err(CoreError::Conflict(
+                "You already reported this review".to_string(),
+            )),
+            result => {
+                result?;
+                Ok(json! ({
                     "code":"ok",
-                    "msg":"App deleted from personal library"
-                })
-            }
-            Err(_) => {
-                json! ({
-                    "code":"err",
-                    "msg":"Unknown error"
-                })
+                    "msg":"The review is reported"
+                }))
             }
         }
     }
 
-    pub async fn add_app_to_personal_library(
+    /// Reported reviews with their open reports, most reported first, then oldest report first
+    pub async fn moderation_queue(&self, page: &PageQuery) -> CoreResult<Page> {
+        let page = PageRequest::new(
+            page,
+            pagination::sort(&[("count", -1), ("since", 1), ("app_id", 1), ("user_name", 1)]),
+        )?;
+        Ok(self.storage.get_moderation_queue(&page).await?)
+    }
+
+    /// Acts on a reported review and resolves its open reports
+    pub async fn moderate_review(
         &self,
-        name: &String,
-        app: &String,
-    ) -> serde_json::Value {
-        let options = FindOneOptions::builder()
-            .projection(doc! {"_id" : 1})
-            .build();
-        let response = self.apps.find_one(doc! {"app_id":&app}, options).await;
-        match response {
-            Ok(response) => match response {
-                Some(_) => {
-                    let response = self
-                        .personal_libraries
-                        .update_one(
-                            doc! {"name": name},
-                            doc! {"$push": {
-                                "apps":&app,
-                            }},
-                            None,
+        moderator: &str,
+        info: &ModerationInfo,
+    ) -> CoreResult<serde_json::Value> {
+        info.validate()?;
+        let review_not_found = || CoreError::NotFound("Review does not exist".to_string());
+        match info.action {
+            ModerationAction::Hide | ModerationAction::Restore => {
+                let hidden = matches!(info.action, ModerationAction::Hide);
+                self.storage
+                    .update_review(&info.user_name, &info.app_id, doc! {"hidden": hidden})
+                    .await?
+                    .ok_or_else(review_not_found)?;
+            }
+            ModerationAction::Delete => {
+                let review = self
+                    .storage
+                    .delete_review(&info.user_name, &info.app_id)
+                    .await?
+                    .ok_or_else(review_not_found)?;
+                self.storage
+                    .insert_review_revision(review_revision(&review, true))
+                    .await?;
+            }
+            ModerationAction::Warn => {
+                self.storage
+                    .find_review(&info.user_name, &info.app_id)
+                    .await?
+                    .ok_or_else(review_not_found)?;
+                let user = self
+                    .storage
+                    .find_user(&info.user_name)
+                    .await?
+                    .ok_or_else(|| CoreError::NotFound("User does not exist".to_string()))?;
+                let warnings = user.get_i32("warnings").unwrap_or_default() + 1;
+                self.storage
+                    .update_user(&info.user_name, doc! {"warnings": warnings})
+                    .await?;
+                let email = user.get_str("email").unwrap_or_default();
+                if !email.is_empty() {
+                    self.mailer
+                        .send(
+                            email,
+                            "Your OSMA review was reported",
+                            &format!(
+                                "Hello, {}!\n\nYour review of {} was reported and a moderator warned you.{}",
+                                info.user_name,
+                                info.app_id,
+                                info.note
+                                    .as_ref()
+                                    .map(|note| format!("\n\n{}", note))
+                                    .unwrap_or_default()
+                            ),
                         )
-                        .await;
-                    match response {
-                        Ok(_) => {
-                            json! ({
-                                "code":"ok",
-                                "msg":"App added to personal library"
-                            })
-                        }
-                        Err(_) => {
-                            json! ({
-                                "code":"err",
-                                "msg":"App already in the library | Unknown error"
-                            })
-                        }
-                    }
+                        .await
+                        .map_err(CoreError::Internal)?;
                 }
-                None => {
-                    json! ({
-                        "code":"denied",
-                        "msg":"This app does not exist"
-                    })
-                }
-            },
-            Err(_) => {
-                json! ({
-                    "code":"err",
-                    "msg":"Unknown error"
-                })
             }
         }
+
+        self.storage
+            .resolve_review_reports(
+                &info.app_id,
+                &info.user_name,
+                doc! {
+                    "status": "resolved",
+                    "action": bson::to_bson(&info.action).map_err(|e| CoreError::Internal(Box::new(e)))?,
+                    "moderator": moderator,
+                    "resolved": Utc::now().timestamp(),
+                },
+            )
+            .await?;
+
+        Ok(json! ({
+            "code":"ok",
+            "msg":"The review is moderated"
+        }))
     }
 
-    pub async fn change_password(
+    /// Current review of the user, `null` if deleted, with its earlier revisions
+    pub async fn get_review_history(
         &self,
-        name: &String,
-        old: &String,
-        new: &String,
-    ) -> serde_json::Value {
-        let response = self.users.find_one(doc! {"name":&name}, None).await;
-        match response {
-            Ok(user) => match user {
-                Some(user) => {
-                    let old_pass_hash = self.hash(name.clone() + &old);
-                    if &old_pass_hash == user.get_str("password").unwrap() {
-                        let response = self
-                            .users
-                            .update_one(
-                                doc! {"name": name},
-                                doc! {"$set": {
-                                    "password":&self.hash(name.clone() + &new),
-                                }},
-                                None,
-                            )
-                            .await;
-                        match response {
-                            Ok(_) => {
-                                json! ({
-                                    "code":"ok",
-                                    "msg":"User information updated"
-                                })
-                            }
-                            Err(_) => {
-                                json! ({
-                                    "code":"err",
-                                    "msg":"Some error"
-                                })
-                            }
-                        }
-                    } else {
-                        json! ({
-                            "code":"denied",
-                            "msg":"Wrong password"
-                        })
-                    }
-                }
-                None => {
-                    json! ({
-                        "code":"err",
-                        "msg":"User does not exist"
-                    })
-                }
-            },
-            Err(_e) => {
-                json! ({
-                    "code":"err",
-                    "msg":"User does not exist"
-                })
+        app_id: &str,
+        user_name: &str,
+    ) -> CoreResult<serde_json::Value> {
+        let mut review = self.storage.find_review(user_name, app_id).await?;
+        if let Some(review) = review.as_mut() {
+            review.remove("_id");
+        }
+        let revisions = self.storage.get_review_revisions(user_name, app_id).await?;
+
+        Ok(json!({
+            "review": review,
+            "revisions": revisions,
+        }))
+    }
+
+    pub async fn update_user(
+        &self,
+        name: &str,
+        info: &Json<UserData>,
+    ) -> CoreResult<serde_json::Value> {
+        info.validate()?;
+        let user = self.find_user(name).await?;
+        let email_changed = user.get_str("email").unwrap_or_default() != info.email;
+        if email_changed {
+            check_confirmation_cooldown(&user)?;
+        }
+
+        let mut fields = doc! {
+            "email":&info.email,
+            "img":&info.img
+        };
+        if email_changed {
+            fields.insert("confirmed", false);
+        }
+        self.storage.update_user(name, fields).await?;
+
+        if email_changed {
+            self.send_confirmation(name, &info.email).await?;
+        }
+
+        Ok(json! ({
+            "code":"ok",
+            "msg":"User information updated"
+        }))
+    }
+
+    pub async fn get_latest_version(
+        &self,
+        app_id: &str,
+        query: &VersionsQuery,
+    ) -> CoreResult<Document> {
+        let versions = versions::sort(self.storage.get_versions(app_id).await?, query.prerelease);
+        if versions.is_empty() {
+            return Err(CoreError::NotFound(
+                "Versions of this app does not exists".to_string(),
+            ));
+        }
+
+        let target = target(&query.platform, &query.arch, &query.package_type);
+        latest_build(versions, target).ok_or_else(|| {
+            CoreError::NotFound("There is no build of this app for your platform".to_string())
+        })
+    }
+
+    /// Url of the best build of the version, the download is recorded in the background
+    pub async fn download(
+        &self,
+        name: Option<&str>,
+        address: &str,
+        app_id: &str,
+        version: &str,
+        query: &VersionsQuery,
+    ) -> CoreResult<String>
+    where
+        S: 'static,
+    {
+        let builds: Vec<Document> = self
+            .storage
+            .get_versions(app_id)
+            .await?
+            .into_iter()
+            .filter(|v| v.get_str("version").unwrap_or_default() == version)
+            .collect();
+        if builds.is_empty() {
+            return Err(CoreError::NotFound("Version does not exist".to_string()));
+        }
+
+        let build = latest_build(
+            builds,
+            target(&query.platform, &query.arch, &query.package_type),
+        )
+        .ok_or_else(|| {
+            CoreError::NotFound("There is no build of this app for your platform".to_string())
+        })?;
+
+        let download = doc! {
+            "user_name": downloader(name, address),
+            "app_id": app_id,
+            "version": version,
+            "platform": build.get_str("platform").unwrap_or_default(),
+            "timestamp": Utc::now().timestamp(),
+        };
+        let storage = self.storage.clone();
+        actix_web::rt::spawn(async move {
+            let _ = storage.record_download(download).await;
+        });
+
+        Ok(build.get_str("url").unwrap_or_default().to_string())
+    }
+
+    /// Unique downloads of the app in the last day, week and all time, by version and platform
+    pub async fn get_download_stats(&self, app_id: &str) -> CoreResult<serde_json::Value> {
+        let now = Utc::now().timestamp();
+        let counts = self
+            .storage
+            .get_download_counts(app_id, now - DAY, now - 7 * DAY)
+            .await?;
+
+        let mut total = [0; 3];
+        let mut by_version: HashMap<&str, [i64; 3]> = HashMap::new();
+        let mut by_platform: HashMap<&str, [i64; 3]> = HashMap::new();
+        for count in &counts {
+            let count_of = |window| match count.get(window) {
+                Some(Bson::Int32(n)) => *n as i64,
+                Some(Bson::Int64(n)) => *n,
+                _ => 0,
+            };
+            let windows = [count_of("day"), count_of("week"), count_of("all")];
+            let version = by_version
+                .entry(count.get_str("version").unwrap_or_default())
+                .or_default();
+            let platform = by_platform
+                .entry(count.get_str("platform").unwrap_or_default())
+                .or_default();
+            for i in 0..3 {
+                total[i] += windows[i];
+                version[i] += windows[i];
+                platform[i] += windows[i];
             }
         }
+
+        let mut versions: Vec<Document> = by_version
+            .into_iter()
+            .map(|(version, [day, week, all])| doc! {"version": version, "day": day, "week": week, "all": all})
+            .collect();
+        versions.sort_by(versions::newest_first);
+        let mut platforms: Vec<Document> = by_platform
+            .into_iter()
+            .map(|(platform, [day, week, all])| doc! {"platform": platform, "day": day, "week": week, "all": all})
+            .collect();
+        platforms.sort_by_key(|p| std::cmp::Reverse(p.get_i64("all").unwrap_or_default()));
+
+        Ok(json!({
+            "app_id": app_id,
+            "day": total[0],
+            "week": total[1],
+            "all": total[2],
+            "versions": versions,
+            "platforms": platforms,
+        }))
+    }
+
+    /// Newer releases of the installed apps, apps that are up to date or unknown are left out
+    pub async fn check_updates(&self, info: &UpdatesInfo) -> CoreResult<Vec<serde_json::Value>> {
+        info.validate()?;
+
+        let app_ids: Vec<String> = info.apps.iter().map(|app| app.app_id.clone()).collect();
+        let released = self.storage.get_versions_of_apps(&app_ids).await?;
+
+        let mut updates = vec![];
+        for app in &info.apps {
+            let versions = released
+                .iter()
+                .filter(|v| v.get_str("app_id").unwrap_or_default() == app.app_id)
+                .cloned()
+                .collect();
+            let target = target(&app.platform, &app.arch, &app.package_type);
+            let latest = match latest_build(versions::sort(versions, info.prerelease), target) {
+                Some(latest) => latest,
+                None => continue,
+            };
+
+            let version = latest.get_str("version").unwrap_or_default();
+            if versions::is_newer(version, &app.installed_version) {
+                updates.push(json!({
+                    "app_id": &app.app_id,
+                    "installed_version": &app.installed_version,
+                    "version": version,
+                    "url": latest.get_str("url").unwrap_or_default(),
+                    "platform": latest.get_str("platform").unwrap_or_default(),
+                    "arch": latest.get_str("arch").unwrap_or_default(),
+                    "type": latest.get_str("type").unwrap_or_default(),
+                    "changelog": versions::excerpt(
+                        latest.get_str("changelog").unwrap_or_default(),
+                        CHANGELOG_EXCERPT_CHARS,
+                    ),
+                }));
+            }
+        }
+        Ok(updates)
+    }
+
+    pub async fn delete_app_from_personal_library(
+        &self,
+        name: &str,
+        app: &str,
+    ) -> CoreResult<serde_json::Value> {
+        self.storage.delete_from_personal_library(name, app).await?;
+
+        Ok(json! ({
+            "code":"ok",
+            "msg":"App deleted from personal library"
+        }))
+    }
+
+    pub async fn add_app_to_personal_library(
+        &self,
+        name: &str,
+        app: &str,
+    ) -> CoreResult<serde_json::Value> {
+        self.get_app(app).await?;
+
+        match self.storage.add_to_personal_library(name, app).await {
+            Ok(_) => Ok(json! ({
+                "code":"ok",
+                "msg":"App added to personal library"
+            })),
+            Err(StorageError::Duplicate) => Err(CoreError::Conflict(
+                "App already in the library".to_string(),
+            )),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    pub async fn change_password(
+        &self,
+        name: &str,
+        info: &PasswordsInf,
+    ) -> CoreResult<serde_json::Value> {
+        info.validate()?;
+        let (old, new) = (&info.old_password, &info.new_password);
+        let user = self.find_user(name).await?;
+
+        if let PasswordCheck::Wrong = self.check_password(name, old, &user) {
+            return Err(CoreError::Unauthorized("Wrong password".to_string()));
+        }
+
+        self.storage
+            .update_user(
+                name,
+                doc! {
+                    "password":self.hash(new)?,
+                },
+            )
+            .await?;
+
+        Ok(json! ({
+            "code":"ok",
+            "msg":"User information updated"
+        }))
     }
 
-    pub async fn signin(&self, name: &String, password: &String) -> serde_json::Value {
-        let response = self.users.find_one(doc! {"name":name}, None).await;
-        match response {
-            Ok(user) => {
-                match user {
-                    Some(user) => {
-                        let pass_hash = self.hash(name.clone() + &password);
-                        if user.get_str("password").unwrap() == pass_hash {
-                            let jwt_info = JwtInfo {
-                                name: name.clone(),
-                                role: user.get_str("role").unwrap().to_string(),
-                                exp: Utc::now().timestamp() + 604800, //week
-                            };
-
-                            let token = encode(
-                                &Header::default(),
-                                &jwt_info,
-                                &EncodingKey::from_secret(self.jwt_secret.as_ref()),
-                            );
-
-                            match token {
-                                Ok(token) => {
-                                    json! ({
-                                        "code":"ok",
-                                        "token":token
-                                    })
-                                }
-                                Err(_) => {
-                                    json! ({
-                                        "code":"err",
-                                        "msg":"Some problem with jwt generation"
-                                    })
-                                }
-                            }
-                        } else {
-                            json! ({
-                                "code":"denied",
-                                "msg":"Wrong password"
-                            })
-                        }
-                    }
-                    None => {
-                        json! ({
-                            "code":"err",
-                            "msg":"User does not exist"
-                        })
-                    }
+    pub async fn signin(&self, info: &UserAuth) -> CoreResult<serde_json::Value> {
+        info.validate()?;
+        let (name, password) = (info.name.as_str(), info.password.as_str());
+        let user = self.find_user(name).await?;
+
+        match self.check_password(name, password, &user) {
+            PasswordCheck::Valid => {}
+            PasswordCheck::Legacy => {
+                //silently move the user to argon2, the old hash keeps working if this fails
+                if let Ok(hash) = self.hash(password) {
+                    let _ = self
+                        .storage
+                        .update_user(name, doc! {"password": hash})
+                        .await;
                 }
             }
-            Err(_) => {
-                json! ({
-                    "code":"err",
-                    "msg":"User does not exist"
-                })
+            PasswordCheck::Wrong => {
+                return Err(CoreError::Unauthorized("Wrong password".to_string()))
             }
         }
+
+        self.start_session(name, user.get_str("role").unwrap_or("user"))
+            .await
+    }
+
+    pub async fn signup(&self, user: &Json<User>) -> CoreResult<serde_json::Value> {
+        user.validate()?;
+        let auth_info = doc! {
+            "name": &user.name,
+            "password": self.hash(&user.password)?,
+            "email": &user.email,
+            "role": "user".to_string(),
+            "confirmed": false,
+        };
+
+        match self.storage.insert_user(auth_info).await {
+            Ok(_) => {}
+            Err(StorageError::Duplicate) => {
+                return Err(CoreError::Conflict(
+                    "User with this name already exist".to_string(),
+                ))
+            }
+            Err(e) => return Err(e.into()),
+        }
+        self.create_personal_library(&user.name).await?;
+        //a failed delivery must not fail the signup, the mail can be resent
+        let _ = self.send_confirmation(&user.name, &user.email).await;
+
+        self.start_session(&user.name, "user").await
+    }
+
+    pub async fn confirm_email(&self, info: &TokenInfo) -> CoreResult<serde_json::Value> {
+        info.validate()?;
+        let token = info.token.as_str();
+        let invalid = || CoreError::Unauthorized("Invalid confirmation token".to_string());
+        let info = decode::<ConfirmationInfo>(
+            token,
+            &DecodingKey::from_secret(self.jwt_secret.as_ref()),
+            &Validation::default(),
+        )
+        .map_err(|_| invalid())?
+        .claims;
+
+        let user = self.find_user(&info.name).await.map_err(|_| invalid())?;
+        if user.get_str("confirmation_nonce").unwrap_or_default() != token_hash(&info.nonce)
+            || user.get_str("email").unwrap_or_default() != info.email
+        {
+            return Err(invalid());
+        }
+
+        self.storage
+            .update_user(
+                &info.name,
+                doc! {
+                    "confirmed": true,
+                    "confirmation_nonce": Bson::Null,
+                },
+            )
+            .await?;
+
+        Ok(json! ({
+            "code":"ok",
+            "msg":"Email confirmed"
+        }))
+    }
+
+    pub async fn resend_confirmation(&self, name: &str) -> CoreResult<serde_json::Value> {
+        let user = self.find_user(name).await?;
+        if user.get_bool("confirmed").unwrap_or(true) {
+            return Err(CoreError::Conflict(
+                "Email is already confirmed".to_string(),
+            ));
+        }
+        check_confirmation_cooldown(&user)?;
+
+        self.send_confirmation(name, user.get_str("email").unwrap_or_default())
+            .await?;
+
+        Ok(json! ({
+            "code":"ok",
+            "msg":"Confirmation sent"
+        }))
+    }
+
+    /// Mails a confirmation link, previously sent links stop working
+    async fn send_confirmation(&self, name: &str, email: &str) -> CoreResult<()> {
+        let nonce = random_token();
+        let info = ConfirmationInfo {
+            name: name.to_string(),
+            email: email.to_string(),
+            nonce: nonce.clone(),
+            exp: Utc::now().timestamp() + CONFIRMATION_TTL,
+        };
+        let token = encode(
+            &Header::default(),
+            &info,
+            &EncodingKey::from_secret(self.jwt_secret.as_ref()),
+        )?;
+
+        self.storage
+            .update_user(
+                name,
+                doc! {
+                    "confirmation_nonce": token_hash(&nonce),
+                    "confirmation_sent": Utc::now().timestamp(),
+                },
+            )
+            .await?;
+
+        self.mailer
+            .send(
+                email,
+                "Confirm your OSMA account",
+                &format!(
+                    "Hello, {}!\n\nOpen the link to confirm your email: {}/auth/confirm?token={}",
+                    name, self.public_url, token
+                ),
+            )
+            .await
+            .map_err(CoreError::Internal)
+    }
+
+    pub async fn refresh(&self, info: &RefreshInfo) -> CoreResult<serde_json::Value> {
+        info.validate()?;
+        let session = self.find_session(&info.refresh_token).await?;
+        let session_id = session.get_str("session_id").unwrap_or_default();
+        let user = self
+            .find_user(session.get_str("name").unwrap_or_default())
+            .await?;
+
+        let secret = random_token();
+        let rotated = self
+            .storage
+            .rotate_session(
+                session_id,
+                session.get_str("refresh_hash").unwrap_or_default(),
+                doc! {
+                    "refresh_hash": token_hash(&secret),
+                    "expires": Utc::now().timestamp() + REFRESH_TOKEN_TTL,
+                },
+            )
+            .await?;
+        if !rotated {
+            return Err(invalid_refresh_token());
+        }
+
+        self.tokens(
+            user.get_str("name").unwrap_or_default(),
+            user.get_str("role").unwrap_or("user"),
+            session_id,
+            &secret,
+        )
+    }
+
+    pub async fn logout(&self, info: &RefreshInfo) -> CoreResult<serde_json::Value> {
+        info.validate()?;
+        let session = self.find_session(&info.refresh_token).await?;
+        self.storage
+            .delete_session(session.get_str("session_id").unwrap_or_default())
+            .await?;
+
+        Ok(json! ({
+            "code":"ok",
+            "msg":"Logged out"
+        }))
+    }
+
+    pub async fn logout_all(&self, info: &RefreshInfo) -> CoreResult<serde_json::Value> {
+        info.validate()?;
+        let session = self.find_session(&info.refresh_token).await?;
+        self.storage
+            .delete_user_sessions(session.get_str("name").unwrap_or_default())
+            .await?;
+
+        Ok(json! ({
+            "code":"ok",
+            "msg":"Logged out on all devices"
+        }))
+    }
+
+    /// Whether the session of an access token was not revoked or expired
+    pub async fn session_active(&self, session_id: &str) -> bool {
+        match self.storage.find_session(session_id).await {
+            Ok(Some(session)) => session.get_i64("expires").unwrap_or(0) > Utc::now().timestamp(),
+            _ => false,
+        }
     }
 
-    pub async fn signup(&self, user: &Json<User>) -> serde_json::Value {
+    async fn start_session(&self, name: &str, role: &str) -> CoreResult<serde_json::Value> {
+        let session_id = random_token();
+        let secret = random_token();
+        self.storage
+            .insert_session(doc! {
+                "session_id": &session_id,
+                "name": name,
+                "refresh_hash": token_hash(&secret),
+                "expires": Utc::now().timestamp() + REFRESH_TOKEN_TTL,
+                "timestamp": Utc::now().timestamp(),
+            })
+            .await?;
+
+        self.tokens(name, role, &session_id, &secret)
+    }
+
+    /// Looks up the session of a `session_id.secret` refresh token
+    async fn find_session(&self, refresh_token: &str) -> CoreResult<Document> {
+        let (session_id, secret) = refresh_token
+            .split_once('.')
+            .ok_or_else(invalid_refresh_token)?;
+        let session = self
+            .storage
+            .find_session(session_id)
+            .await?
+            .ok_or_else(invalid_refresh_token)?;
+
+        if session.get_str("refresh_hash").unwrap_or_default() != token_hash(secret)
+            || session.get_i64("expires").unwrap_or(0) <= Utc::now().timestamp()
+        {
+            return Err(invalid_refresh_token());
+        }
+        Ok(session)
+    }
+
+    fn tokens(
+        &self,
+        name: &str,
+        role: &str,
+        session_id: &str,
+        secret: &str,
+    ) -> CoreResult<serde_json::Value> {
         let jwt_info = JwtInfo {
-            name: user.name.clone(),
-            role: "user".to_string(),
-            exp: Utc::now().timestamp() + 604800, //week
+            name: name.to_string(),
+            role: role.to_string(),
+            jti: session_id.to_string(),
+            exp: Utc::now().timestamp() + ACCESS_TOKEN_TTL,
         };
 
         let token = encode(
             &Header::default(),
             &jwt_info,
             &EncodingKey::from_secret(self.jwt_secret.as_ref()),
-        );
+        )?;
 
-        match token {
-            Ok(token) => {
-                let auth_info = doc! {
-                    "name": &user.name,
-                    "password": self.hash(user.name.clone() + &user.password),
-                    "email": &user.email,
-                    "role": "user".to_string(),
-                };
-                let response = self.users.insert_one(&auth_info, None).await;
-                match response {
-                    Ok(_) => {
-                        self.create_personal_library(&user.name).await;
-                        json! ({
-                            "code":"ok",
-                            "token":token
-                        })
-                    }
-                    Err(_) => {
-                        json! ({
-                            "code":"err",
-                            "msg":"User with this name already exist"
-                        })
-                    }
-                }
-            }
-            Err(_) => {
-                json! ({
-                    "code":"err",
-                    "msg":"Some problem with jwt generation"
-                })
+        Ok(json! ({
+            "code":"ok",
+            "token":token,
+            "refresh_token":format!("{}.{}", session_id, secret)
+        }))
+    }
+
+    /// Answers at once and the same for every login, the mail is sent in the background
+    pub async fn forgot_password(&self, info: &ForgotPasswordInfo) -> CoreResult<serde_json::Value>
+    where
+        S: 'static,
+    {
+        info.validate()?;
+        let storage = self.storage.clone();
+        let mailer = self.mailer.clone();
+        let login = info.login.clone();
+        actix_web::rt::spawn(async move {
+            let _ = send_password_reset(storage.as_ref(), mailer.as_ref(), &login).await;
+        });
+
+        Ok(json! ({
+            "code":"ok",
+            "msg":"If the account exists, a password reset link was sent to its email"
+        }))
+    }
+
+    pub async fn reset_password(&self, info: &ResetPasswordInfo) -> CoreResult<serde_json::Value> {
+        info.validate()?;
+        let (token, new) = (info.token.as_str(), info.new_password.as_str());
+        let invalid = || CoreError::Unauthorized("Invalid password reset token".to_string());
+        let user = self
+            .storage
+            .find_user_by("reset_hash", &token_hash(token))
+            .await?
+            .ok_or_else(invalid)?;
+        if user.get_i64("reset_expires").unwrap_or(0) <= Utc::now().timestamp() {
+            return Err(invalid());
+        }
+
+        let name = user.get_str("name").unwrap_or_default();
+        self.storage
+            .update_user(
+                name,
+                doc! {
+                    "password": self.hash(new)?,
+                    "reset_hash": Bson::Null,
+                    "reset_expires": Bson::Null,
+                },
+            )
+            .await?;
+        self.storage.delete_user_sessions(name).await?;
+
+        Ok(json! ({
+            "code":"ok",
+            "msg":"Password changed"
+        }))
+    }
+
+    pub async fn become_developer(&self, name: &str) -> CoreResult<serde_json::Value> {
+        let user = self.find_user(name).await?;
+        match user.get_str("role").unwrap_or("user") {
+            "developer" | "admin" => {
+                return Err(CoreError::Conflict(
+                    "You are already a developer".to_string(),
+                ))
             }
+            _ => {}
         }
+        if !user.get_bool("confirmed").unwrap_or(true) {
+            return Err(CoreError::Forbidden(
+                "Confirm your email to become a developer".to_string(),
+            ));
+        }
+
+        self.storage
+            .update_user(name, doc! {"role": "developer"})
+            .await?;
+
+        Ok(json! ({
+            "code":"ok",
+            "msg":"You are a developer now, refresh the token to use it"
+        }))
     }
-    fn hash(&self, to_hash: String) -> String {
-        let mut hasher = Sha3_256::new();
-        hasher.update(to_hash + &self.salt);
-        let hash = hasher.finalize();
-        format!("{:x}", hash)
+
+    pub async fn get_developer_apps(&self, name: &str, page: &PageQuery) -> CoreResult<Page> {
+        let page = PageRequest::new(page, apps_sort())?;
+        Ok(self.storage.get_apps_by_author(name, &page).await?)
     }
 
-    async fn get_collection_with_params_and_sort(
-        &self,
-        collection: &Collection<Document>,
-        params: Document,
-        sort_params: Document,
-    ) -> Result<Vec<Document>, serde_json::Value> {
-        let options = FindOptions::builder()
-            .projection(doc! {"_id" : 0})
-            .sort(sort_params)
-            .build();
-        let cursor = match collection.find(params, options).await {
-            Ok(cursor) => cursor,
-            Err(_) => {
-                return Err(json! ({
-                    "code":"err",
-                    "msg":"Error connecting to the database"
-                }))
-            }
-        };
+    pub async fn create_app(&self, name: &str, app: &AppData) -> CoreResult<serde_json::Value> {
+        app.validate()?;
+        let mut document = app_document(app);
+        document.insert("tags", self.taxonomy().await?.normalize(&app.tags));
+        document.insert("rating", Aggregate::default().to_document());
+        document.insert("author", name);
+        document.insert("published", true);
 
-        match cursor.try_collect().await {
-            Ok(collection) => Ok(collection),
-            Err(_) => Err(json! ({
-                "code":"err",
-                "msg":"Error connecting to the database"
+        match self.storage.insert_app(document).await {
+            Ok(_) => Ok(json! ({
+                "code":"ok",
+                "msg":"App created"
             })),
+            Err(StorageError::Duplicate) => Err(CoreError::Conflict(
+                "App with this id already exist".to_string(),
+            )),
+            Err(e) => Err(e.into()),
         }
     }
 
-    async fn get_collection_with_params(
+    pub async fn edit_app(
         &self,
-        collection: &Collection<Document>,
-        params: Document,
-    ) -> Result<Vec<Document>, serde_json::Value> {
-        let options = FindOptions::builder().projection(doc! {"_id" : 0}).build();
-        let cursor = match collection.find(params, options).await {
-            Ok(cursor) => cursor,
-            Err(_) => {
-                return Err(json! ({
-                    "code":"err",
-                    "msg":"Error connecting to the database"
-                }))
+        name: &str,
+        role: &str,
+        app: &AppData,
+    ) -> CoreResult<serde_json::Value> {
+        app.validate()?;
+        self.find_owned_app(name, role, &app.app_id).await?;
+        let mut document = app_document(app);
+        document.insert("tags", self.taxonomy().await?.normalize(&app.tags));
+        self.storage.update_app(&app.app_id, document).await?;
+
+        Ok(json! ({
+            "code":"ok",
+            "msg":"App updated"
+        }))
+    }
+
+    pub async fn set_app_published(
+        &self,
+        name: &str,
+        role: &str,
+        app_id: &str,
+        published: bool,
+    ) -> CoreResult<serde_json::Value> {
+        self.find_owned_app(name, role, app_id).await?;
+        self.storage
+            .update_app(app_id, doc! {"published": published})
+            .await?;
+
+        Ok(json! ({
+            "code":"ok",
+            "msg":if published { "App published" } else { "App unpublished" }
+        }))
+    }
+
+    pub async fn publish_version(
+        &self,
+        name: &str,
+        role: &str,
+        info: &VersionData,
+    ) -> CoreResult<serde_json::Value> {
+        info.validate()?;
+        self.find_owned_app(name, role, &info.app_id).await?;
+
+        let released = self.storage.get_versions(&info.app_id).await?;
+        if released.iter().any(|v| {
+            v.get_str("version").unwrap_or_default() == info.version
+                && v.get_str("platform").unwrap_or_default() == info.platform
+                && v.get_str("arch").unwrap_or_default() == info.arch
+                && v.get_str("type").unwrap_or_default() == info.package_type
+        }) {
+            return Err(CoreError::Conflict(
+                "This version is already released for the platform".to_string(),
+            ));
+        }
+
+        self.storage
+            .insert_version(doc! {
+                "app_id": &info.app_id,
+                "version": &info.version,
+                "url": &info.url,
+                "platform": &info.platform,
+                "arch": &info.arch,
+                "type": &info.package_type,
+                "changelog": &info.changelog,
+                "timestamp": Utc::now().timestamp(),
+            })
+            .await?;
+
+        Ok(json! ({
+            "code":"ok",
+            "msg":"Version released"
+        }))
+    }
+
+    /// The tag catalog as it is stored
+    async fn taxonomy(&self) -> CoreResult<Taxonomy> {
+        Ok(Taxonomy::new(self.storage.get_tags().await?))
+    }
+
+    /// Tag query with aliases resolved, with `children` tags also match the tags below them
+    async fn tag_filter(&self, info: &AppTags) -> CoreResult<TagFilter> {
+        let taxonomy = self.taxonomy().await?;
+        let expand = |tag: &String| {
+            let slug = taxonomy.resolve(tag);
+            match info.children {
+                true => taxonomy.with_children(&slug),
+                false => vec![slug],
             }
         };
+        Ok(TagFilter {
+            any: info.tags.iter().flat_map(expand).collect(),
+            all: info.all.iter().map(expand).collect(),
+            exclude: info.exclude.iter().flat_map(expand).collect(),
+        })
+    }
 
-        match cursor.try_collect().await {
-            Ok(collection) => Ok(collection),
-            Err(_) => Err(json! ({
-                "code":"err",
-                "msg":"Error connecting to the database"
-            })),
+    /// The app if `name` is its author, admins own every app
+    async fn find_owned_app(&self, name: &str, role: &str, app_id: &str) -> CoreResult<Document> {
+        let app = self.get_app(app_id).await?;
+        if role != "admin" && app.get_str("author").unwrap_or_default() != name {
+            return Err(CoreError::Forbidden(
+                "You are not the author of this app".to_string(),
+            ));
         }
+        Ok(app)
     }
 
-    async fn get_collection(
-        &self,
-        collection: &Collection<Document>,
-    ) -> Result<Vec<Document>, serde_json::Value> {
-        let options = FindOptions::builder().projection(doc! {"_id" : 0}).build();
-        let cursor = match collection.find(None, options).await {
-            Ok(cursor) => cursor,
+    async fn find_user(&self, name: &str) -> CoreResult<Document> {
+        match self.storage.find_user(name).await? {
+            Some(user) => Ok(user),
+            None => Err(CoreError::NotFound("User does not exist".to_string())),
+        }
+    }
+
+    /// Accounts from before email confirmation have no flag and count as confirmed
+    async fn require_confirmed(&self, name: &str, action: &str) -> CoreResult<()> {
+        if !self
+            .find_user(name)
+            .await?
+            .get_bool("confirmed")
+            .unwrap_or(true)
+        {
+            return Err(CoreError::Forbidden(format!(
+                "Confirm your email to {}",
+                action
+            )));
+        }
+        Ok(())
+    }
+
+    /// Argon2id hash with a random salt in PHC string format
+    fn hash(&self, password: &str) -> CoreResult<String> {
+        let salt = SaltString::generate(&mut OsRng);
+        Ok(Argon2::default()
+            .hash_password(password.as_bytes(), &salt)?
+            .to_string())
+    }
+
+    /// Accounts created before argon2 store `sha3(name + password + SALT)`
+    fn legacy_hash(&self, to_hash: String) -> String {
+        let mut hasher = Sha3_256::new();
+        hasher.update(to_hash + &self.salt);
+        let hash = hasher.finalize();
+        format!("{:x}", hash)
+    }
+
+    fn check_password(&self, name: &str, password: &str, user: &Document) -> PasswordCheck {
+        let stored = user.get_str("password").unwrap_or_default();
+        match PasswordHash::new(stored) {
+            Ok(hash) => match Argon2::default().verify_password(password.as_bytes(), &hash) {
+                Ok(_) => PasswordCheck::Valid,
+                Err(_) => PasswordCheck::Wrong,
+            },
             Err(_) => {
-                return Err(json! ({
-                    "code":"err",
-                    "msg":"Error connecting to the database"
-                }))
+                if self.legacy_hash(name.to_string() + password) == stored {
+                    PasswordCheck::Legacy
+                } else {
+                    PasswordCheck::Wrong
+                }
             }
-        };
+        }
+    }
+}
 
-        match cursor.try_collect().await {
-            Ok(collection) => Ok(collection),
-            Err(_) => Err(json! ({
-                "code":"err",
-                "msg":"Error connecting to the database"
-            })),
+enum PasswordCheck {
+    Valid,
+    /// Correct password stored with the legacy sha3 hash
+    Legacy,
+    Wrong,
+}
+
+fn target(
+    platform: &Option<String>,
+    arch: &Option<String>,
+    package_type: &Option<String>,
+) -> Option<versions::Target> {
+    platform
+        .as_ref()
+        .map(|platform| versions::Target::new(platform, arch.as_deref(), package_type.as_deref()))
+}
+
+/// Best build of the newest release from versions sorted newest first
+fn latest_build(versions: Vec<Document>, target: Option<versions::Target>) -> Option<Document> {
+    match target {
+        Some(target) => target.pick(versions),
+        None => versions.into_iter().next(),
+    }
+}
+
+/// Ties are broken by the newest review, then by the author
+fn reviews_sort(sort: ReviewSort) -> pagination::Sort {
+    pagination::sort(match sort {
+        ReviewSort::Newest => &[("timestamp", -1), ("user_name", 1)],
+        ReviewSort::Oldest => &[("timestamp", 1), ("user_name", 1)],
+        ReviewSort::Highest => &[("score", -1), ("timestamp", -1), ("user_name", 1)],
+        ReviewSort::Lowest => &[("score", 1), ("timestamp", -1), ("user_name", 1)],
+        ReviewSort::Helpful => &[("helpful", -1), ("timestamp", -1), ("user_name", 1)],
+    })
+}
+
+fn apps_sort() -> pagination::Sort {
+    pagination::sort(&[("app_id", 1)])
+}
+
+/// Fields of an app a developer can set, `author` and `published` are managed separately
+fn app_document(app: &AppData) -> Document {
+    doc! {
+        "app_id": &app.app_id,
+        "name": &app.name,
+        "description": &app.description,
+        "icon": &app.icon,
+        "screenshots": &app.screenshots,
+        "repo": &app.repo,
+        "tags": &app.tags,
+    }
+}
+
+/// Catalog entry of a validated tag, the slug, parent and aliases must not clash with other tags
+fn tag_document(taxonomy: &Taxonomy, tag: &TagData) -> CoreResult<Document> {
+    if taxonomy
+        .owner(&tag.slug)
+        .is_some_and(|owner| owner != tag.slug)
+    {
+        return Err(CoreError::Conflict(format!(
+            "{} is an alias of another tag",
+            tag.slug
+        )));
+    }
+
+    if let Some(parent) = &tag.parent {
+        if taxonomy.find(parent).is_none() {
+            return Err(CoreError::Validation(
+                "Parent tag does not exist".to_string(),
+            ));
+        }
+        if taxonomy.with_children(&tag.slug).contains(parent) {
+            return Err(CoreError::Validation(
+                "A tag can not be below itself".to_string(),
+            ));
+        }
+    }
+
+    let mut aliases: Vec<String> = vec![];
+    for alias in tag.aliases.iter().map(|alias| taxonomy::slugify(alias)) {
+        if alias.is_empty() || alias == tag.slug || aliases.contains(&alias) {
+            continue;
         }
+        if let Some(owner) = taxonomy.owner(&alias).filter(|owner| *owner != tag.slug) {
+            return Err(CoreError::Conflict(format!(
+                "{} is already used by tag {}",
+                alias, owner
+            )));
+        }
+        aliases.push(alias);
+    }
+
+    Ok(doc! {
+        "slug": &tag.slug,
+        "name": tag.name.trim(),
+        "parent": &tag.parent,
+        "aliases": aliases,
+    })
+}
+
+/// State of a review before it was edited or deleted
+fn review_revision(review: &Document, deleted: bool) -> Document {
+    doc! {
+        "app_id": review.get_str("app_id").unwrap_or_default(),
+        "user_name": review.get_str("user_name").unwrap_or_default(),
+        "text": review.get("text").cloned(),
+        "score": review.get("score").cloned(),
+        "version": review.get("version").cloned(),
+        "timestamp": review.get("timestamp").cloned(),
+        "replaced": Utc::now().timestamp(),
+        "deleted": deleted,
+    }
+}
+
+/// Mails a reset token to the user with the name or email
+async fn send_password_reset<S: Storage + ?Sized>(
+    storage: &S,
+    mailer: &dyn MailSender,
+    login: &str,
+) -> CoreResult<()> {
+    let user = match storage.find_user(login).await? {
+        Some(user) => user,
+        None => storage
+            .find_user_by("email", login)
+            .await?
+            .ok_or_else(|| CoreError::NotFound("User does not exist".to_string()))?,
+    };
+    let sent = user.get_i64("reset_sent").unwrap_or(0);
+    if sent + RESET_COOLDOWN > Utc::now().timestamp() {
+        return Err(CoreError::RateLimited(
+            "Password reset was requested recently".to_string(),
+        ));
     }
+
+    let name = user.get_str("name").unwrap_or_default();
+    let token = random_token();
+    storage
+        .update_user(
+            name,
+            doc! {
+                "reset_hash": token_hash(&token),
+                "reset_expires": Utc::now().timestamp() + RESET_TTL,
+                "reset_sent": Utc::now().timestamp(),
+            },
+        )
+        .await?;
+
+    mailer
+        .send(
+            user.get_str("email").unwrap_or_default(),
+            "Reset your OSMA password",
+            &format!(
+                "Hello, {}!\n\nUse this token to set a new password, it is valid for an hour: {}\n\nIf you did not ask for it, ignore this mail.",
+                name, token
+            ),
+        )
+        .await
+        .map_err(CoreError::Internal)
+}
+
+/// Every confirmation mail counts, whether it was resent or sent for a new email
+fn check_confirmation_cooldown(user: &Document) -> CoreResult<()> {
+    let sent = user.get_i64("confirmation_sent").unwrap_or(0);
+    if sent + CONFIRMATION_COOLDOWN > Utc::now().timestamp() {
+        return Err(CoreError::RateLimited(
+            "Confirmation was sent recently, try again later".to_string(),
+        ));
+    }
+    Ok(())
 }
+
+/// Users count by name, visitors by a hash of their address that no user name can match
+fn downloader(name: Option<&str>, address: &str) -> String {
+    match name {
+        Some(name) => name.to_string(),
+        None => format!("<{}", &token_hash(address)[..15]),
+    }
+}
+
+fn tag_not_found() -> CoreError {
+    CoreError::NotFound("Tag does not exist".to_string())
+}
+
+fn invalid_refresh_token() -> CoreError {
+    CoreError::Unauthorized("Invalid refresh token".to_string())
+}
+
+/// 256 random bits as hex
+fn random_token() -> String {
+    let mut bytes = [0u8; 32];
+    OsRng.fill_bytes(&mut bytes);
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Refresh tokens are random, so a plain hash is enough to not store them as is
+fn token_hash(token: &str) -> String {
+    format!("{:x}", Sha3_256::digest(token.as_bytes()))
+}
+
+fn app_not_found() -> CoreError {
+    CoreError::NotFound("This app does not exist".to_string())
+}
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,784 @@
+// Copyright (c) 2023 artegoser (Artemy Egorov)
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+use super::*;
+use crate::storage::memory::MemoryStorage;
+use std::sync::Mutex;
+
+/// Keeps the bodies of sent mails
+#[derive(Default)]
+struct Outbox(Mutex<Vec<String>>);
+
+#[async_trait::async_trait]
+impl MailSender for Outbox {
+    async fn send(&self, _to: &str, _subject: &str, body: &str) -> crate::mail::MailResult {
+        self.0.lock().unwrap().push(body.to_string());
+        Ok(())
+    }
+}
+
+/// Core over an empty in-memory storage
+fn core() -> (Core<MemoryStorage>, Arc<Outbox>) {
+    env::set_var("JWT_SECRET", "test secret");
+    env::set_var("SALT", "test salt");
+    let outbox = Arc::new(Outbox::default());
+    (
+        Core::new(Arc::new(MemoryStorage::new()), outbox.clone()),
+        outbox,
+    )
+}
+
+mod libraries {
+    use super::*;
+
+    async fn setup() -> Core<MemoryStorage> {
+        let (core, _) = core();
+        core.storage
+            .insert_app(doc! {"app_id": "edit", "name": "Editor"})
+            .await
+            .unwrap();
+        let user = User {
+            name: "alice".to_string(),
+            password: "secret".to_string(),
+            email: "alice@example.org".to_string(),
+        };
+        core.signup(&Json(user)).await.unwrap();
+        core
+    }
+
+    #[actix_rt::test]
+    async fn adds_and_removes_apps() {
+        let core = setup().await;
+        assert!(core.get_personal_library("alice").await.unwrap().is_empty());
+
+        core.add_app_to_personal_library("alice", "edit")
+            .await
+            .unwrap();
+        assert!(matches!(
+            core.add_app_to_personal_library("alice", "none").await,
+            Err(CoreError::NotFound(_))
+        ));
+        assert_eq!(
+            core.get_personal_library("alice").await.unwrap(),
+            [Bson::String("edit".to_string())]
+        );
+
+        core.delete_app_from_personal_library("alice", "edit")
+            .await
+            .unwrap();
+        assert!(core.get_personal_library("alice").await.unwrap().is_empty());
+    }
+
+    #[actix_rt::test]
+    async fn rejects_apps_already_added() {
+        let core = setup().await;
+        core.add_app_to_personal_library("alice", "edit")
+            .await
+            .unwrap();
+        assert!(matches!(
+            core.add_app_to_personal_library("alice", "edit").await,
+            Err(CoreError::Conflict(_))
+        ));
+    }
+
+    #[actix_rt::test]
+    async fn creates_missing_libraries() {
+        let core = setup().await;
+        core.add_app_to_personal_library("bob", "edit")
+            .await
+            .unwrap();
+        assert_eq!(core.get_personal_library("bob").await.unwrap().len(), 1);
+    }
+
+    #[actix_rt::test]
+    async fn unknown_users_have_no_library() {
+        let core = setup().await;
+        assert!(matches!(
+            core.get_personal_library("bob").await,
+            Err(CoreError::NotFound(_))
+        ));
+    }
+}
+
+mod errors {
+    use super::*;
+
+    #[actix_rt::test]
+    async fn wrong_passwords_are_unauthorized() {
+        let (core, _) = core();
+        let user = User {
+            name: "alice".to_string(),
+            password: "secret".to_string(),
+            email: "alice@example.org".to_string(),
+        };
+        core.signup(&Json(user)).await.unwrap();
+
+        let auth = UserAuth {
+            name: "alice".to_string(),
+            password: "wrong".to_string(),
+        };
+        assert!(matches!(
+            core.signin(&auth).await,
+            Err(CoreError::Unauthorized(_))
+        ));
+        let passwords = PasswordsInf {
+            old_password: "wrong".to_string(),
+            new_password: "new secret".to_string(),
+        };
+        assert!(matches!(
+            core.change_password("alice", &passwords).await,
+            Err(CoreError::Unauthorized(_))
+        ));
+        let auth = UserAuth {
+            password: "secret".to_string(),
+            ..auth
+        };
+        core.signin(&auth).await.unwrap();
+    }
+}
+
+mod passwords {
+    use super::*;
+
+    fn auth(password: &str) -> UserAuth {
+        UserAuth {
+            name: "alice".to_string(),
+            password: password.to_string(),
+        }
+    }
+
+    #[actix_rt::test]
+    async fn moves_legacy_hashes_to_argon2() {
+        let (core, _) = core();
+        let legacy = core.legacy_hash("alice".to_string() + "secret");
+        core.storage
+            .insert_user(doc! {"name": "alice", "password": &legacy, "role": "user"})
+            .await
+            .unwrap();
+
+        assert!(core.signin(&auth("wrong")).await.is_err());
+        core.signin(&auth("secret")).await.unwrap();
+        let user = core.find_user("alice").await.unwrap();
+        assert!(user.get_str("password").unwrap().starts_with("$argon2id$"));
+        core.signin(&auth("secret")).await.unwrap();
+    }
+}
+
+mod sessions {
+    use super::*;
+
+    fn refresh(refresh_token: &str) -> RefreshInfo {
+        RefreshInfo {
+            refresh_token: refresh_token.to_string(),
+        }
+    }
+
+    async fn signin(core: &Core<MemoryStorage>) -> String {
+        let user = User {
+            name: "alice".to_string(),
+            password: "secret".to_string(),
+            email: "alice@example.org".to_string(),
+        };
+        let session = core.signup(&Json(user)).await.unwrap();
+        session["refresh_token"].as_str().unwrap().to_string()
+    }
+
+    #[actix_rt::test]
+    async fn rotates_refresh_tokens() {
+        let (core, _) = core();
+        let old_token = signin(&core).await;
+        let session = core.refresh(&refresh(&old_token)).await.unwrap();
+        assert!(session["token"].is_string());
+        assert!(core.refresh(&refresh(&old_token)).await.is_err());
+        core.refresh(&refresh(session["refresh_token"].as_str().unwrap()))
+            .await
+            .unwrap();
+    }
+
+    #[actix_rt::test]
+    async fn revokes_sessions() {
+        let (core, _) = core();
+        let first = signin(&core).await;
+        let auth = UserAuth {
+            name: "alice".to_string(),
+            password: "secret".to_string(),
+        };
+        let second = core.signin(&auth).await.unwrap()["refresh_token"]
+            .as_str()
+            .unwrap()
+            .to_string();
+        let third = core.signin(&auth).await.unwrap()["refresh_token"]
+            .as_str()
+            .unwrap()
+            .to_string();
+
+        core.logout(&refresh(&first)).await.unwrap();
+        assert!(core.refresh(&refresh(&first)).await.is_err());
+        assert!(core.refresh(&refresh("garbage")).await.is_err());
+        let second = core.refresh(&refresh(&second)).await.unwrap()["refresh_token"]
+            .as_str()
+            .unwrap()
+            .to_string();
+
+        core.logout_all(&refresh(&second)).await.unwrap();
+        assert!(core.refresh(&refresh(&second)).await.is_err());
+        assert!(core.refresh(&refresh(&third)).await.is_err());
+    }
+}
+
+mod confirmation {
+    use super::*;
+
+    fn link(token: &str) -> TokenInfo {
+        TokenInfo {
+            token: token.to_string(),
+        }
+    }
+
+    async fn signup(core: &Core<MemoryStorage>) {
+        let user = User {
+            name: "alice".to_string(),
+            password: "secret".to_string(),
+            email: "alice@example.org".to_string(),
+        };
+        core.signup(&Json(user)).await.unwrap();
+    }
+
+    fn link_token(outbox: &Outbox) -> String {
+        let mail = outbox.0.lock().unwrap().last().unwrap().clone();
+        mail.split("token=").nth(1).unwrap().trim().to_string()
+    }
+
+    #[actix_rt::test]
+    async fn confirms_emails_once() {
+        let (core, outbox) = core();
+        signup(&core).await;
+        assert!(matches!(
+            core.resend_confirmation("alice").await,
+            Err(CoreError::RateLimited(_))
+        ));
+
+        let token = link_token(&outbox);
+        assert!(core.confirm_email(&link("invalid")).await.is_err());
+        core.confirm_email(&link(&token)).await.unwrap();
+        assert!(core.confirm_email(&link(&token)).await.is_err());
+        assert!(matches!(
+            core.resend_confirmation("alice").await,
+            Err(CoreError::Conflict(_))
+        ));
+    }
+
+    #[actix_rt::test]
+    async fn gates_writes_on_confirmation() {
+        let (core, outbox) = core();
+        signup(&core).await;
+        core.storage
+            .insert_app(doc! {"app_id": "edit", "name": "Editor"})
+            .await
+            .unwrap();
+        let review = ReviewData {
+            app_id: "edit".to_string(),
+            score: 8,
+            text: "Fast and simple".to_string(),
+            version: None,
+        };
+        let review = Json(review);
+        assert!(matches!(
+            core.write_review("alice", &review).await,
+            Err(CoreError::Forbidden(_))
+        ));
+        assert!(matches!(
+            core.become_developer("alice").await,
+            Err(CoreError::Forbidden(_))
+        ));
+
+        core.confirm_email(&link(&link_token(&outbox)))
+            .await
+            .unwrap();
+        core.write_review("alice", &review).await.unwrap();
+        core.become_developer("alice").await.unwrap();
+    }
+
+    #[actix_rt::test]
+    async fn confirms_changed_emails() {
+        let (core, outbox) = core();
+        signup(&core).await;
+        let old_token = link_token(&outbox);
+        let data = UserData {
+            email: "alice@example.com".to_string(),
+            img: String::new(),
+        };
+        assert!(matches!(
+            core.update_user("alice", &Json(data)).await,
+            Err(CoreError::RateLimited(_))
+        ));
+
+        core.storage
+            .update_user("alice", doc! {"confirmation_sent": 0})
+            .await
+            .unwrap();
+        let data = UserData {
+            email: "alice@example.com".to_string(),
+            img: String::new(),
+        };
+        core.update_user("alice", &Json(data)).await.unwrap();
+        assert!(core.confirm_email(&link(&old_token)).await.is_err());
+        core.confirm_email(&link(&link_token(&outbox)))
+            .await
+            .unwrap();
+    }
+}
+
+mod recovery {
+    use super::*;
+
+    /// The reset mail is sent in the background
+    async fn reset_token(outbox: &Outbox) -> String {
+        for _ in 0..100 {
+            if let Some(mail) = outbox.0.lock().unwrap().last() {
+                let token = mail.split("hour: ").nth(1).unwrap();
+                return token.split_whitespace().next().unwrap().to_string();
+            }
+            actix_rt::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+        panic!("no reset mail was sent");
+    }
+
+    fn reset(token: &str, password: &str) -> ResetPasswordInfo {
+        ResetPasswordInfo {
+            token: token.to_string(),
+            new_password: password.to_string(),
+        }
+    }
+
+    #[actix_rt::test]
+    async fn resets_passwords_once() {
+        let (core, outbox) = core();
+        core.storage
+            .insert_user(doc! {"name": "alice", "email": "alice@example.org", "role": "user"})
+            .await
+            .unwrap();
+        let forgot = ForgotPasswordInfo {
+            login: "alice@example.org".to_string(),
+        };
+        core.forgot_password(&forgot).await.unwrap();
+        let token = reset_token(&outbox).await;
+
+        assert!(core.reset_password(&reset("invalid", "new")).await.is_err());
+        core.reset_password(&reset(&token, "new")).await.unwrap();
+        assert!(core.reset_password(&reset(&token, "newer")).await.is_err());
+        let auth = UserAuth {
+            name: "alice".to_string(),
+            password: "new".to_string(),
+        };
+        core.signin(&auth).await.unwrap();
+    }
+
+    #[actix_rt::test]
+    async fn answers_the_same_for_unknown_logins() {
+        let (core, outbox) = core();
+        let forgot = ForgotPasswordInfo {
+            login: "nobody".to_string(),
+        };
+        let answer = core.forgot_password(&forgot).await.unwrap();
+        assert_eq!(answer["code"], "ok");
+        actix_rt::time::sleep(std::time::Duration::from_millis(50)).await;
+        assert!(outbox.0.lock().unwrap().is_empty());
+    }
+}
+
+mod downloads {
+    use super::*;
+
+    async fn setup() -> Core<MemoryStorage> {
+        let (core, _) = core();
+        core.storage
+            .insert_version(doc! {
+                "app_id": "edit",
+                "version": "1.0.0",
+                "platform": "linux",
+                "url": "https://example.org/edit.AppImage",
+                "timestamp": 1,
+            })
+            .await
+            .unwrap();
+        core
+    }
+
+    async fn download(core: &Core<MemoryStorage>, name: Option<&str>, address: &str) -> String {
+        let query: VersionsQuery = serde_json::from_value(json!({})).unwrap();
+        core.download(name, address, "edit", "1.0.0", &query)
+            .await
+            .unwrap()
+    }
+
+    /// Downloads are recorded in the background
+    async fn total(core: &Core<MemoryStorage>, expected: i64) {
+        for _ in 0..100 {
+            if core.get_download_stats("edit").await.unwrap()["all"] == expected {
+                return;
+            }
+            actix_rt::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+        panic!("expected {} downloads", expected);
+    }
+
+    #[actix_rt::test]
+    async fn counts_each_downloader_once() {
+        let core = setup().await;
+        let url = download(&core, Some("alice"), "10.0.0.1").await;
+        assert_eq!(url, "https://example.org/edit.AppImage");
+        download(&core, Some("alice"), "10.0.0.2").await;
+        download(&core, None, "10.0.0.1").await;
+        download(&core, None, "10.0.0.1").await;
+        download(&core, None, "10.0.0.3").await;
+        total(&core, 3).await;
+    }
+
+    #[actix_rt::test]
+    async fn rejects_unknown_versions() {
+        let core = setup().await;
+        let query: VersionsQuery = serde_json::from_value(json!({})).unwrap();
+        assert!(matches!(
+            core.download(None, "10.0.0.1", "edit", "2.0.0", &query)
+                .await,
+            Err(CoreError::NotFound(_))
+        ));
+        let query: VersionsQuery = serde_json::from_value(json!({"platform": "windows"})).unwrap();
+        assert!(matches!(
+            core.download(None, "10.0.0.1", "edit", "1.0.0", &query)
+                .await,
+            Err(CoreError::NotFound(_))
+        ));
+    }
+
+    #[test]
+    fn visitors_can_not_match_users() {
+        let visitor = downloader(None, "10.0.0.1");
+        assert!(visitor.starts_with('<'));
+        assert_eq!(visitor.len(), 16);
+        assert_eq!(visitor, downloader(None, "10.0.0.1"));
+        assert_ne!(visitor, downloader(None, "10.0.0.2"));
+        assert_eq!(downloader(Some("alice"), "10.0.0.1"), "alice");
+    }
+}
+
+mod reviews {
+    use super::*;
+
+    async fn setup() -> Core<MemoryStorage> {
+        let (core, _) = core();
+        core.storage
+            .insert_app(doc! {"app_id": "edit", "name": "Editor"})
+            .await
+            .unwrap();
+        core.storage
+            .insert_user(doc! {"name": "alice", "role": "user"})
+            .await
+            .unwrap();
+        core
+    }
+
+    fn review(score: i32, text: &str) -> Json<ReviewData> {
+        Json(ReviewData {
+            app_id: "edit".to_string(),
+            score,
+            text: text.to_string(),
+            version: None,
+        })
+    }
+
+    #[actix_rt::test]
+    async fn keeps_one_review_per_user_with_history() {
+        let core = setup().await;
+        core.write_review("alice", &review(4, "Crashes sometimes"))
+            .await
+            .unwrap();
+        core.write_review("alice", &review(9, "Fixed now, great"))
+            .await
+            .unwrap();
+        assert_eq!(core.get_user_reviews("alice").await.unwrap().len(), 1);
+
+        let history = core.get_review_history("edit", "alice").await.unwrap();
+        assert_eq!(history["review"]["score"], 9);
+        let revisions = history["revisions"].as_array().unwrap();
+        assert_eq!(revisions.len(), 1);
+        assert_eq!(revisions[0]["score"], 4);
+    }
+
+    #[actix_rt::test]
+    async fn deletes_own_reviews() {
+        let core = setup().await;
+        core.write_review("alice", &review(8, "Fast and simple"))
+            .await
+            .unwrap();
+        core.delete_review("alice", "edit").await.unwrap();
+        assert!(core.get_user_reviews("alice").await.unwrap().is_empty());
+        assert!(matches!(
+            core.delete_review("alice", "edit").await,
+            Err(CoreError::NotFound(_))
+        ));
+
+        let history = core.get_review_history("edit", "alice").await.unwrap();
+        assert!(history["review"].is_null());
+        assert_eq!(history["revisions"].as_array().unwrap().len(), 1);
+    }
+}
+
+mod ratings {
+    use super::*;
+
+    async fn setup() -> Core<MemoryStorage> {
+        let (core, _) = core();
+        for app_id in ["edit", "mail"] {
+            core.storage
+                .insert_app(doc! {"app_id": app_id, "rating": Aggregate::default().to_document()})
+                .await
+                .unwrap();
+        }
+        for name in ["alice", "bob"] {
+            core.storage
+                .insert_user(doc! {"name": name, "role": "user"})
+                .await
+                .unwrap();
+        }
+        core
+    }
+
+    async fn review(core: &Core<MemoryStorage>, name: &str, app_id: &str, score: i32) {
+        let review = ReviewData {
+            app_id: app_id.to_string(),
+            score,
+            text: format!("Score {} from {}", score, name),
+            version: None,
+        };
+        core.write_review(name, &Json(review)).await.unwrap();
+    }
+
+    #[actix_rt::test]
+    async fn keeps_ratings_with_reviews() {
+        let core = setup().await;
+        assert_eq!(core.get_rating("edit").await.unwrap().count, 0);
+
+        review(&core, "alice", "edit", 8).await;
+        review(&core, "bob", "edit", 4).await;
+        let rating = core.get_rating("edit").await.unwrap();
+        assert_eq!((rating.count, rating.average), (2, Some(6.0)));
+
+        review(&core, "bob", "edit", 10).await;
+        let rating = core.get_rating("edit").await.unwrap();
+        assert_eq!((rating.count, rating.average), (2, Some(9.0)));
+        assert_eq!(rating.histogram[&4], 0);
+
+        core.delete_review("alice", "edit").await.unwrap();
+        let rating = core.get_rating("edit").await.unwrap();
+        assert_eq!((rating.count, rating.average), (1, Some(10.0)));
+    }
+
+    #[actix_rt::test]
+    async fn keeps_totals_of_all_apps() {
+        let core = setup().await;
+        review(&core, "alice", "edit", 8).await;
+        review(&core, "alice", "mail", 2).await;
+        review(&core, "bob", "mail", 5).await;
+        assert_eq!(core.storage.get_rating_totals().await.unwrap(), (3, 15));
+
+        review(&core, "bob", "mail", 9).await;
+        core.delete_review("alice", "mail").await.unwrap();
+        assert_eq!(core.storage.get_rating_totals().await.unwrap(), (2, 17));
+        let rating = core.get_rating("edit").await.unwrap();
+        assert_eq!(rating.weighted, Some((8.5 * 10.0 + 8.0) / 11.0));
+    }
+
+    #[actix_rt::test]
+    async fn rebuilds_ratings() {
+        let core = setup().await;
+        review(&core, "alice", "edit", 8).await;
+        core.storage.set_ratings(Default::default()).await.unwrap();
+        assert_eq!(core.storage.get_rating_totals().await.unwrap(), (0, 0));
+
+        assert_eq!(core.rebuild_ratings().await.unwrap(), 1);
+        assert_eq!(core.get_rating("edit").await.unwrap().count, 1);
+        assert_eq!(core.storage.get_rating_totals().await.unwrap(), (1, 8));
+    }
+}
+
+mod moderation {
+    use super::*;
+
+    /// Reviews of `edit` by alice and bob, reported by the other users
+    async fn setup() -> Core<MemoryStorage> {
+        let (core, _) = core();
+        core.storage
+            .insert_app(doc! {"app_id": "edit", "name": "Editor"})
+            .await
+            .unwrap();
+        for name in ["alice", "bob", "carol", "dave"] {
+            core.storage
+                .insert_user(doc! {"name": name, "role": "user"})
+                .await
+                .unwrap();
+        }
+        for name in ["alice", "bob"] {
+            let review = ReviewData {
+                app_id: "edit".to_string(),
+                score: 1,
+                text: format!("Bad app, says {}", name),
+                version: None,
+            };
+            core.write_review(name, &Json(review)).await.unwrap();
+        }
+        for (reporter, author) in [("carol", "alice"), ("carol", "bob"), ("dave", "bob")] {
+            let report = ReviewReport {
+                app_id: "edit".to_string(),
+                user_name: author.to_string(),
+                reason: ReportReason::Spam,
+                comment: None,
+            };
+            core.report_review(reporter, &report).await.unwrap();
+        }
+        core
+    }
+
+    fn page(cursor: Option<String>) -> PageQuery {
+        PageQuery {
+            limit: Some(1),
+            cursor,
+            count: true,
+        }
+    }
+
+    #[actix_rt::test]
+    async fn pages_through_the_most_reported_first() {
+        let core = setup().await;
+        let first = core.moderation_queue(&page(None)).await.unwrap();
+        assert_eq!(first.total, Some(2));
+        assert_eq!(first.items[0].get_str("user_name"), Ok("bob"));
+        assert_eq!(first.items[0].get_i64("count"), Ok(2));
+        assert_eq!(first.items[0].get_array("reports").unwrap().len(), 2);
+        let review = first.items[0].get_document("review").unwrap();
+        assert_eq!(review.get_str("text"), Ok("Bad app, says bob"));
+
+        let second = core.moderation_queue(&page(first.next)).await.unwrap();
+        assert_eq!(second.items[0].get_str("user_name"), Ok("alice"));
+        assert!(second.next.is_none());
+    }
+
+    #[actix_rt::test]
+    async fn resolves_reports() {
+        let core = setup().await;
+        let info = ModerationInfo {
+            app_id: "edit".to_string(),
+            user_name: "bob".to_string(),
+            action: ModerationAction::Hide,
+            note: None,
+        };
+        core.moderate_review("admin", &info).await.unwrap();
+        assert_eq!(core.get_rating("edit").await.unwrap().count, 1);
+
+        let queue = core.moderation_queue(&page(None)).await.unwrap();
+        assert_eq!(queue.total, Some(1));
+        assert_eq!(queue.items[0].get_str("user_name"), Ok("alice"));
+    }
+
+    #[actix_rt::test]
+    async fn rejects_reports_on_own_reviews() {
+        let core = setup().await;
+        let report = ReviewReport {
+            app_id: "edit".to_string(),
+            user_name: "alice".to_string(),
+            reason: ReportReason::Other,
+            comment: None,
+        };
+        assert!(matches!(
+            core.report_review("alice", &report).await,
+            Err(CoreError::Forbidden(_))
+        ));
+    }
+}
+
+mod screening {
+    use super::*;
+
+    async fn setup() -> Core<MemoryStorage> {
+        let (core, _) = core();
+        core.storage
+            .insert_app(doc! {"app_id": "edit", "name": "Editor"})
+            .await
+            .unwrap();
+        core.storage
+            .insert_user(doc! {"name": "alice", "role": "user"})
+            .await
+            .unwrap();
+        core
+    }
+
+    fn review(text: &str) -> Json<ReviewData> {
+        Json(ReviewData {
+            app_id: "edit".to_string(),
+            score: 9,
+            text: text.to_string(),
+            version: None,
+        })
+    }
+
+    #[actix_rt::test]
+    async fn rejects_without_storing() {
+        let core = setup().await;
+        assert!(matches!(
+            core.write_review("alice", &review("Great!!!!!!!!!!!!"))
+                .await,
+            Err(CoreError::Validation(_))
+        ));
+        assert!(core.get_user_reviews("alice").await.unwrap().is_empty());
+        assert_eq!(core.get_rating("edit").await.unwrap().count, 0);
+    }
+
+    #[actix_rt::test]
+    async fn holds_for_moderation() {
+        let core = setup().await;
+        let links = "See https://a.org, https://b.org and https://c.org";
+        let answer = core.write_review("alice", &review(links)).await.unwrap();
+        assert!(answer["msg"].as_str().unwrap().contains("moderation"));
+        assert_eq!(core.get_rating("edit").await.unwrap().count, 0);
+
+        let queue = PageQuery {
+            limit: None,
+            cursor: None,
+            count: false,
+        };
+        let queue = core.moderation_queue(&queue).await.unwrap();
+        let report = &queue.items[0].get_array("reports").unwrap()[0];
+        assert_eq!(
+            report.as_document().unwrap().get_str("user_name"),
+            Ok(SCREENING_REPORTER)
+        );
+    }
+
+    #[actix_rt::test]
+    async fn counts_edits_in_the_rate() {
+        let core = setup().await;
+        for i in 0..Rules::default().max_reviews {
+            core.write_review("alice", &review(&format!("Edit number {}", i)))
+                .await
+                .unwrap();
+        }
+        assert!(matches!(
+            core.write_review("alice", &review("One edit too many"))
+                .await,
+            Err(CoreError::Validation(_))
+        ));
+    }
+}
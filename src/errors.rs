@@ -0,0 +1,129 @@
+// Copyright (c) 2023 artegoser (Artemy Egorov)
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+use actix_web::{http::StatusCode, HttpResponse, ResponseError};
+use serde_json::json;
+use std::fmt;
+
+use crate::storage::StorageError;
+use crate::validation::FieldError;
+
+pub type CoreResult<T> = Result<T, CoreError>;
+
+#[derive(Debug)]
+pub enum CoreError {
+    NotFound(String),
+    Conflict(String),
+    Unauthorized(String),
+    Forbidden(String),
+    Validation(String),
+    /// Request body fields that failed validation
+    Fields(Vec<FieldError>),
+    RateLimited(String),
+    Internal(Box<dyn std::error::Error + Send + Sync>),
+}
+
+impl CoreError {
+    /// Stable identifier of the error kind, clients should match on it instead of `msg`
+    pub fn code(&self) -> &'static str {
+        match self {
+            CoreError::NotFound(_) => "not_found",
+            CoreError::Conflict(_) => "conflict",
+            CoreError::Unauthorized(_) => "unauthorized",
+            CoreError::Forbidden(_) => "forbidden",
+            CoreError::Validation(_) | CoreError::Fields(_) => "validation",
+            CoreError::RateLimited(_) => "rate_limited",
+            CoreError::Internal(_) => "internal",
+        }
+    }
+
+    fn msg(&self) -> &str {
+        match self {
+            CoreError::NotFound(msg)
+            | CoreError::Conflict(msg)
+            | CoreError::Unauthorized(msg)
+            | CoreError::Forbidden(msg)
+            | CoreError::Validation(msg)
+            | CoreError::RateLimited(msg) => msg,
+            CoreError::Fields(_) => "Some fields are invalid",
+            CoreError::Internal(_) => "Internal server error",
+        }
+    }
+}
+
+impl fmt::Display for CoreError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CoreError::Internal(e) => write!(f, "{}: {}", self.code(), e),
+            _ => write!(f, "{}: {}", self.code(), self.msg()),
+        }
+    }
+}
+
+impl std::error::Error for CoreError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            CoreError::Internal(e) => Some(e.as_ref()),
+            _ => None,
+        }
+    }
+}
+
+impl ResponseError for CoreError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            CoreError::NotFound(_) => StatusCode::NOT_FOUND,
+            CoreError::Conflict(_) => StatusCode::CONFLICT,
+            CoreError::Unauthorized(_) => StatusCode::UNAUTHORIZED,
+            CoreError::Forbidden(_) => StatusCode::FORBIDDEN,
+            CoreError::Validation(_) | CoreError::Fields(_) => StatusCode::UNPROCESSABLE_ENTITY,
+            CoreError::RateLimited(_) => StatusCode::TOO_MANY_REQUESTS,
+            CoreError::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        let mut body = json!({
+            "error": self.code(),
+            "msg": self.msg(),
+        });
+        if let CoreError::Fields(fields) = self {
+            body["fields"] = json!(fields);
+        }
+        HttpResponse::build(self.status_code()).json(body)
+    }
+}
+
+impl From<StorageError> for CoreError {
+    fn from(e: StorageError) -> Self {
+        match e {
+            StorageError::Duplicate => CoreError::Conflict("Already exists".to_string()),
+            StorageError::Invalid => CoreError::Validation("Validation error".to_string()),
+            StorageError::Backend(e) => CoreError::Internal(e),
+        }
+    }
+}
+
+impl From<jsonwebtoken::errors::Error> for CoreError {
+    fn from(e: jsonwebtoken::errors::Error) -> Self {
+        CoreError::Internal(Box::new(e))
+    }
+}
+
+impl From<argon2::password_hash::Error> for CoreError {
+    fn from(e: argon2::password_hash::Error) -> Self {
+        CoreError::Internal(Box::new(e))
+    }
+}
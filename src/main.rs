@@ -17,6 +17,7 @@ use actix_cors::Cors;
 use actix_web::{web, App, Error, HttpServer};
 use dotenv::dotenv;
 use std::env;
+use std::sync::Arc;
 
 use actix_web::dev::ServiceRequest;
 use actix_web::http::header::HeaderName;
@@ -27,8 +28,18 @@ use actix_web_httpauth::middleware::HttpAuthentication;
 use jsonwebtoken::{decode, DecodingKey, Validation};
 
 mod core;
+mod errors;
+mod mail;
+mod pagination;
+mod rating;
 mod routes;
+mod screening;
+mod search;
+mod storage;
+mod taxonomy;
 mod types;
+mod validation;
+mod versions;
 
 pub struct AppState {
     core: core::Core,
@@ -39,7 +50,7 @@ async fn jwt_validator(
     credentials: BearerAuth,
 ) -> Result<ServiceRequest, (Error, ServiceRequest)> {
     let token = decode::<types::JwtInfo>(
-        &credentials.token(),
+        credentials.token(),
         &DecodingKey::from_secret(
             env::var("JWT_SECRET")
                 .expect("JWT_SECRET not found")
@@ -47,50 +58,153 @@ async fn jwt_validator(
         ),
         &Validation::default(),
     );
-    match token {
+    let claims = match token {
         Ok(token) => {
-            req.attach(vec![token.claims.role]);
+            let app_data = req.app_data::<web::Data<AppState>>().unwrap().clone();
+            if app_data.core.session_active(&token.claims.jti).await {
+                Some(token.claims)
+            } else {
+                None
+            }
+        }
+        Err(_) => None,
+    };
+    match claims {
+        Some(claims) => {
+            req.attach(permissions(&claims.role));
             req.headers_mut().insert(
                 HeaderName::from_lowercase(b"osma-username").unwrap(),
-                HeaderValue::from_str(&token.claims.name).unwrap(),
+                HeaderValue::from_str(&claims.name).unwrap(),
+            );
+            req.headers_mut().insert(
+                HeaderName::from_lowercase(b"osma-role").unwrap(),
+                HeaderValue::from_str(&claims.role).unwrap(),
             );
 
             Ok(req)
         }
-        Err(_) => {
+        None => {
             req.attach(vec!["none".to_string()]);
             req.headers_mut().insert(
                 HeaderName::from_lowercase(b"osma-username").unwrap(),
                 HeaderValue::from_str("no").unwrap(),
             );
+            req.headers_mut().insert(
+                HeaderName::from_lowercase(b"osma-role").unwrap(),
+                HeaderValue::from_str("none").unwrap(),
+            );
             Ok(req)
         }
     }
 }
 
+/// Developers can do everything a user can
+fn permissions(role: &str) -> Vec<String> {
+    match role {
+        "developer" => vec!["user".to_string(), "developer".to_string()],
+        _ => vec![role.to_string()],
+    }
+}
+
+async fn connect_storage() -> Arc<dyn storage::Storage> {
+    match env::var("STORAGE").as_deref() {
+        Ok("memory") => match env::var("MEMORY_SEED") {
+            Ok(path) => Arc::new(
+                storage::MemoryStorage::from_seed(&path).expect("Can not read memory seed file"),
+            ),
+            Err(_) => Arc::new(storage::MemoryStorage::new()),
+        },
+        Ok("mongodb") | Err(_) => {
+            let client_options = mongodb::options::ClientOptions::parse(
+                env::var("MONGODB_URI").expect("Mongodb uri not found"),
+            )
+            .await
+            .unwrap();
+            let client = mongodb::Client::with_options(client_options).unwrap();
+            Arc::new(storage::MongoStorage::new(&client.database("osma")))
+        }
+        Ok(other) => panic!("Unknown storage backend: {}", other),
+    }
+}
+
+fn connect_mail() -> Arc<dyn mail::MailSender> {
+    match env::var("MAIL").as_deref() {
+        Ok("smtp") => Arc::new(
+            mail::SmtpSender::new(
+                &env::var("SMTP_URL").expect("SMTP_URL not found"),
+                &env::var("MAIL_FROM").expect("MAIL_FROM not found"),
+            )
+            .expect("Can not configure smtp"),
+        ),
+        Ok("outbox") | Err(_) => Arc::new(mail::OutboxSender::new(env::var("MAIL_OUTBOX").ok())),
+        Ok(other) => panic!("Unknown mail sender: {}", other),
+    }
+}
+
 #[actix_rt::main]
 async fn main() -> std::io::Result<()> {
     dotenv().ok();
 
+    let storage = connect_storage().await;
+    let mailer = connect_mail();
+
+    if env::args().nth(1).as_deref() == Some("migrate") {
+        let apply = env::args().nth(2).as_deref() != Some("--check");
+        let report = storage
+            .migrate(apply)
+            .await
+            .expect("Can not migrate database");
+        if report.is_empty() {
+            println!("Database is up to date");
+        }
+        for line in report.iter() {
+            println!("{}", line);
+        }
+        if !apply && !report.is_empty() {
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+    for line in storage
+        .migrate(true)
+        .await
+        .expect("Can not migrate database")
+    {
+        println!("Migrated {}", line);
+    }
+
+    if env::args().nth(1).as_deref() == Some("rebuild-ratings") {
+        let core = core::Core::new(storage, mailer);
+        match core.rebuild_ratings().await {
+            Ok(rated) => println!("Ratings rebuilt, {} apps have reviews", rated),
+            Err(e) => panic!("Can not rebuild ratings: {}", e),
+        }
+        return Ok(());
+    }
+
     let port: u16 = env::var("PORT")
         .unwrap()
         .parse()
         .expect("PORT must be a number");
 
-    let client_options = mongodb::options::ClientOptions::parse(
-        env::var("MONGODB_URI").expect("Mongodb uri not found"),
-    )
-    .await
-    .unwrap();
-    let client = mongodb::Client::with_options(client_options).unwrap();
-    let db = client.database("osma");
-
     HttpServer::new(move || {
         let cors = Cors::default().allow_any_origin();
         App::new()
             .app_data(web::Data::new(AppState {
-                core: core::Core::new(&db),
+                core: core::Core::new(storage.clone(), mailer.clone()),
             }))
+            .app_data(
+                web::JsonConfig::default()
+                    .error_handler(|e, _| errors::CoreError::Validation(e.to_string()).into()),
+            )
+            .app_data(
+                web::QueryConfig::default()
+                    .error_handler(|e, _| errors::CoreError::Validation(e.to_string()).into()),
+            )
+            .app_data(
+                web::PathConfig::default()
+                    .error_handler(|e, _| errors::CoreError::Validation(e.to_string()).into()),
+            )
             .wrap(cors)
             .service(
                 web::scope("/api")
@@ -103,18 +217,51 @@ async fn main() -> std::io::Result<()> {
                     .service(routes::versions)
                     .service(routes::personal_library)
                     .service(routes::latest_version)
+                    .service(routes::download)
+                    .service(routes::downloads)
+                    .service(routes::moderation_queue)
+                    .service(routes::my_reviews)
                     //post
                     .service(routes::update)
+                    .service(routes::check_updates)
+                    .service(routes::resend_confirmation)
                     .service(routes::change_password)
                     .service(routes::write_review)
+                    .service(routes::delete_review)
+                    .service(routes::vote_review)
+                    .service(routes::review_history)
+                    .service(routes::reply_review)
+                    .service(routes::delete_reply)
+                    .service(routes::report_review)
+                    .service(routes::moderate_review)
                     .service(routes::add_app_to_personal_library)
                     .service(routes::delete_app_from_personal_library)
-                    .service(routes::apps_by_tags),
+                    .service(routes::apps_by_tags)
+                    .service(routes::tag_facets)
+                    .service(routes::tags)
+                    .service(routes::create_tag)
+                    .service(routes::edit_tag)
+                    .service(routes::rename_tag)
+                    .service(routes::merge_tags)
+                    .service(routes::search)
+                    .service(routes::become_developer)
+                    .service(routes::developer_apps)
+                    .service(routes::create_app)
+                    .service(routes::edit_app)
+                    .service(routes::unpublish_app)
+                    .service(routes::publish_app)
+                    .service(routes::publish_version),
             )
             .service(
                 web::scope("/auth")
                     .service(routes::signup)
-                    .service(routes::signin),
+                    .service(routes::signin)
+                    .service(routes::refresh)
+                    .service(routes::logout)
+                    .service(routes::logout_all)
+                    .service(routes::confirm)
+                    .service(routes::forgot_password)
+                    .service(routes::reset_password),
             )
     })
     .bind(("0.0.0.0", port))
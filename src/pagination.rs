@@ -0,0 +1,337 @@
+// Copyright (c) 2023 artegoser (Artemy Egorov)
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use bson::{doc, Bson, Document};
+use serde::Serialize;
+use std::cmp::Ordering;
+
+use crate::errors::{CoreError, CoreResult};
+use crate::types::PageQuery;
+
+pub const DEFAULT_PAGE_SIZE: usize = 20;
+pub const MAX_PAGE_SIZE: usize = 100;
+
+/// Sort fields with `1` for ascending and `-1` for descending order.
+///
+/// The fields together must be unique, so the last one usually is an id.
+pub type Sort = Vec<(String, i32)>;
+
+pub fn sort(fields: &[(&str, i32)]) -> Sort {
+    fields.iter().map(|(f, d)| (f.to_string(), *d)).collect()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Direction {
+    Next,
+    Prev,
+}
+
+/// Position in a listing: the sort key of the item the page starts after (or ends before)
+#[derive(Debug, Clone)]
+pub struct Cursor {
+    pub direction: Direction,
+    pub key: Vec<Bson>,
+}
+
+#[derive(Debug, Clone)]
+pub struct PageRequest {
+    pub sort: Sort,
+    pub limit: usize,
+    pub cursor: Option<Cursor>,
+    pub count: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Page {
+    pub items: Vec<Document>,
+    pub next: Option<String>,
+    pub prev: Option<String>,
+    /// Only counted when asked for
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub total: Option<u64>,
+}
+
+impl PageRequest {
+    pub fn new(query: &PageQuery, sort: Sort) -> CoreResult<PageRequest> {
+        let cursor = match &query.cursor {
+            Some(cursor) => Some(decode(cursor, &sort)?),
+            None => None,
+        };
+        Ok(PageRequest {
+            limit: query
+                .limit
+                .unwrap_or(DEFAULT_PAGE_SIZE)
+                .clamp(1, MAX_PAGE_SIZE),
+            sort,
+            cursor,
+            count: query.count,
+        })
+    }
+
+    pub fn direction(&self) -> Direction {
+        match &self.cursor {
+            Some(cursor) => cursor.direction,
+            None => Direction::Next,
+        }
+    }
+
+    /// Sort in the order items are fetched in, backwards for previous pages
+    pub fn fetch_sort(&self) -> Sort {
+        match self.direction() {
+            Direction::Next => self.sort.clone(),
+            Direction::Prev => self.sort.iter().map(|(f, d)| (f.clone(), -d)).collect(),
+        }
+    }
+
+    pub fn key(&self, item: &Document) -> Vec<Bson> {
+        self.sort
+            .iter()
+            .map(|(field, _)| item.get(field).cloned().unwrap_or(Bson::Null))
+            .collect()
+    }
+
+    /// Builds the page from up to `limit + 1` items fetched in `fetch_sort` order
+    pub fn page(&self, mut items: Vec<Document>, total: Option<u64>) -> Page {
+        let has_more = items.len() > self.limit;
+        items.truncate(self.limit);
+        if self.direction() == Direction::Prev {
+            items.reverse();
+        }
+
+        let (more_next, more_prev) = match self.direction() {
+            Direction::Next => (has_more, self.cursor.is_some()),
+            Direction::Prev => (true, has_more),
+        };
+        let next = match items.last() {
+            Some(last) if more_next => Some(self.encode(Direction::Next, last)),
+            _ => None,
+        };
+        let prev = match items.first() {
+            Some(first) if more_prev => Some(self.encode(Direction::Prev, first)),
+            _ => None,
+        };
+
+        Page {
+            items,
+            next,
+            prev,
+            total,
+        }
+    }
+
+    /// Pages through items that are already filtered, for storages without queries
+    pub fn paginate(&self, mut items: Vec<Document>) -> Page {
+        let total = items.len() as u64;
+        let fetch_sort = self.fetch_sort();
+        items.sort_by(|a, b| compare_keys(&fetch_sort, a, b));
+        if let Some(cursor) = &self.cursor {
+            items.retain(|item| {
+                compare_key(&fetch_sort, &self.key(item), &cursor.key) == Ordering::Greater
+            });
+        }
+        items.truncate(self.limit + 1);
+        self.page(items, self.count.then_some(total))
+    }
+
+    fn encode(&self, direction: Direction, item: &Document) -> String {
+        let cursor = doc! {
+            "d": if direction == Direction::Next { "n" } else { "p" },
+            "s": self.sort.iter().map(|(f, d)| format!("{}:{}", f, d)).collect::<Vec<_>>(),
+            "k": self.key(item),
+        };
+        let mut bytes = vec![];
+        cursor.to_writer(&mut bytes).unwrap();
+        URL_SAFE_NO_PAD.encode(bytes)
+    }
+}
+
+fn decode(cursor: &str, sort: &Sort) -> CoreResult<Cursor> {
+    let invalid = || CoreError::Validation("Invalid cursor".to_string());
+    let bytes = URL_SAFE_NO_PAD.decode(cursor).map_err(|_| invalid())?;
+    let cursor = Document::from_reader(bytes.as_slice()).map_err(|_| invalid())?;
+
+    let cursor_sort: Vec<&str> = cursor
+        .get_array("s")
+        .map_err(|_| invalid())?
+        .iter()
+        .filter_map(|f| f.as_str())
+        .collect();
+    let expected_sort: Vec<String> = sort.iter().map(|(f, d)| format!("{}:{}", f, d)).collect();
+    if cursor_sort != expected_sort {
+        return Err(invalid());
+    }
+
+    let key = cursor.get_array("k").map_err(|_| invalid())?.clone();
+    if key.len() != sort.len() {
+        return Err(invalid());
+    }
+    let direction = match cursor.get_str("d") {
+        Ok("n") => Direction::Next,
+        Ok("p") => Direction::Prev,
+        _ => return Err(invalid()),
+    };
+    Ok(Cursor { direction, key })
+}
+
+fn compare_keys(sort: &Sort, a: &Document, b: &Document) -> Ordering {
+    for (field, direction) in sort {
+        let ordering = compare(
+            a.get(field).unwrap_or(&Bson::Null),
+            b.get(field).unwrap_or(&Bson::Null),
+        );
+        if ordering != Ordering::Equal {
+            return if *direction < 0 {
+                ordering.reverse()
+            } else {
+                ordering
+            };
+        }
+    }
+    Ordering::Equal
+}
+
+fn compare_key(sort: &Sort, a: &[Bson], b: &[Bson]) -> Ordering {
+    for ((_, direction), (a, b)) in sort.iter().zip(a.iter().zip(b)) {
+        let ordering = compare(a, b);
+        if ordering != Ordering::Equal {
+            return if *direction < 0 {
+                ordering.reverse()
+            } else {
+                ordering
+            };
+        }
+    }
+    Ordering::Equal
+}
+
+/// Orders values the way mongodb does for the types used in sort keys
+pub fn compare(a: &Bson, b: &Bson) -> Ordering {
+    fn number(value: &Bson) -> Option<f64> {
+        match value {
+            Bson::Int32(n) => Some(*n as f64),
+            Bson::Int64(n) => Some(*n as f64),
+            Bson::Double(n) => Some(*n),
+            _ => None,
+        }
+    }
+    fn rank(value: &Bson) -> u8 {
+        match value {
+            Bson::Null => 0,
+            Bson::Int32(_) | Bson::Int64(_) | Bson::Double(_) => 1,
+            Bson::String(_) => 2,
+            Bson::Boolean(_) => 3,
+            _ => 4,
+        }
+    }
+
+    match (a, b) {
+        (Bson::String(a), Bson::String(b)) => a.cmp(b),
+        (Bson::Boolean(a), Bson::Boolean(b)) => a.cmp(b),
+        _ => match (number(a), number(b)) {
+            (Some(a), Some(b)) => a.total_cmp(&b),
+            _ => rank(a).cmp(&rank(b)),
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn items() -> Vec<Document> {
+        (1..=5)
+            .map(|i| doc! {"_id": format!("id{}", i), "score": i % 3})
+            .collect()
+    }
+
+    fn request(limit: usize, cursor: Option<String>) -> PageRequest {
+        let query = PageQuery {
+            limit: Some(limit),
+            cursor,
+            count: true,
+        };
+        PageRequest::new(&query, sort(&[("score", -1), ("_id", 1)])).unwrap()
+    }
+
+    fn ids(page: &Page) -> Vec<&str> {
+        page.items
+            .iter()
+            .map(|item| item.get_str("_id").unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn pages_forward_and_back() {
+        let first = request(2, None).paginate(items());
+        assert_eq!(ids(&first), ["id2", "id5"]);
+        assert_eq!(first.total, Some(5));
+        assert!(first.prev.is_none());
+
+        let second = request(2, first.next.clone()).paginate(items());
+        assert_eq!(ids(&second), ["id1", "id4"]);
+
+        let last = request(2, second.next.clone()).paginate(items());
+        assert_eq!(ids(&last), ["id3"]);
+        assert!(last.next.is_none());
+
+        let back = request(2, last.prev.clone()).paginate(items());
+        assert_eq!(ids(&back), ["id1", "id4"]);
+        assert!(back.next.is_some());
+        let start = request(2, back.prev.clone()).paginate(items());
+        assert_eq!(ids(&start), ["id2", "id5"]);
+        assert!(start.prev.is_none());
+    }
+
+    #[test]
+    fn clamps_the_limit() {
+        assert_eq!(request(0, None).limit, 1);
+        assert_eq!(request(1000, None).limit, MAX_PAGE_SIZE);
+    }
+
+    #[test]
+    fn rejects_foreign_cursors() {
+        let next = request(2, None).paginate(items()).next;
+        let query = PageQuery {
+            limit: None,
+            cursor: next,
+            count: false,
+        };
+        assert!(PageRequest::new(&query, sort(&[("_id", 1)])).is_err());
+        assert!(request_with("garbage").is_err());
+    }
+
+    fn request_with(cursor: &str) -> CoreResult<PageRequest> {
+        let query = PageQuery {
+            limit: None,
+            cursor: Some(cursor.to_string()),
+            count: false,
+        };
+        PageRequest::new(&query, sort(&[("_id", 1)]))
+    }
+
+    #[test]
+    fn orders_like_mongodb() {
+        assert_eq!(
+            compare(&Bson::Int32(2), &Bson::Double(1.5)),
+            Ordering::Greater
+        );
+        assert_eq!(compare(&Bson::Null, &Bson::Int64(0)), Ordering::Less);
+        assert_eq!(
+            compare(&Bson::String("a".into()), &Bson::Int32(9)),
+            Ordering::Greater
+        );
+    }
+}
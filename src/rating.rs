@@ -0,0 +1,219 @@
+// Copyright (c) 2023 artegoser (Artemy Egorov)
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+use bson::{doc, Bson, Document};
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+/// How many average reviews the weighted average starts from
+pub const PRIOR_WEIGHT: f64 = 10.0;
+/// Prior when there are no reviews at all, the middle of the 1-10 scale
+const DEFAULT_PRIOR_MEAN: f64 = 5.5;
+
+/// Review counts kept on every app as `rating`, changed together with its reviews
+#[derive(Debug, Default, Clone)]
+pub struct Aggregate {
+    pub count: i64,
+    pub sum: i64,
+    /// Reviews with each score, score 1 first
+    pub histogram: [i64; 10],
+}
+
+impl Aggregate {
+    /// The `rating` of the app, empty if it has none yet
+    pub fn of(app: &Document) -> Aggregate {
+        let rating = match app.get_document("rating") {
+            Ok(rating) => rating,
+            Err(_) => return Aggregate::default(),
+        };
+        let mut histogram = [0; 10];
+        if let Ok(counts) = rating.get_array("histogram") {
+            for (reviews, count) in histogram.iter_mut().zip(counts) {
+                *reviews = number(Some(count)) as i64;
+            }
+        }
+        Aggregate {
+            count: number(rating.get("count")) as i64,
+            sum: number(rating.get("sum")) as i64,
+            histogram,
+        }
+    }
+
+    /// Counts the review, or uncounts it with `-1`, hidden reviews do not count
+    pub fn add(&mut self, review: &Document, sign: i64) {
+        if review.get_bool("hidden").unwrap_or(false) {
+            return;
+        }
+        self.add_score(number(review.get("score")).round() as i64, sign);
+    }
+
+    /// Uncounting a score that was never counted does nothing, counts never go below zero
+    fn add_score(&mut self, score: i64, reviews: i64) {
+        if (1..=10).contains(&score) && self.histogram[score as usize - 1] + reviews >= 0 {
+            self.count += reviews;
+            self.sum += reviews * score;
+            self.histogram[score as usize - 1] += reviews;
+        }
+    }
+
+    pub fn average(&self) -> Option<f64> {
+        match self.count {
+            0 => None,
+            count => Some(self.sum as f64 / count as f64),
+        }
+    }
+
+    /// `average` is stored too, so apps can be filtered and sorted by it
+    pub fn to_document(&self) -> Document {
+        doc! {
+            "count": self.count,
+            "sum": self.sum,
+            "average": self.average(),
+            "histogram": self.histogram.to_vec(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Rating {
+    pub app_id: String,
+    pub count: i64,
+    /// Number of reviews with each score from 1 to 10
+    pub histogram: BTreeMap<u8, i64>,
+    /// `None` without reviews, the same goes for `weighted`
+    pub average: Option<f64>,
+    /// Bayesian average, apps with few reviews are pulled towards the average of all reviews
+    pub weighted: Option<f64>,
+}
+
+impl Rating {
+    pub fn new(app_id: &str, aggregate: &Aggregate, prior_mean: f64) -> Rating {
+        let weighted = match aggregate.count {
+            0 => None,
+            count => Some(
+                (prior_mean * PRIOR_WEIGHT + aggregate.sum as f64) / (PRIOR_WEIGHT + count as f64),
+            ),
+        };
+        Rating {
+            app_id: app_id.to_string(),
+            count: aggregate.count,
+            histogram: (1..=10).zip(aggregate.histogram).collect(),
+            average: aggregate.average(),
+            weighted,
+        }
+    }
+}
+
+/// Aggregates of every app with reviews from `{"app_id", "score", "count"}` documents
+pub fn aggregates(counts: &[Document]) -> BTreeMap<String, Aggregate> {
+    let mut aggregates: BTreeMap<String, Aggregate> = BTreeMap::new();
+    for count in counts {
+        let aggregate = aggregates
+            .entry(count.get_str("app_id").unwrap_or_default().to_string())
+            .or_default();
+        aggregate.add_score(
+            number(count.get("score")).round() as i64,
+            number(count.get("count")) as i64,
+        );
+    }
+    aggregates
+}
+
+/// Average of all reviews from the summed up `count` and `sum` of every app
+pub fn prior_mean(count: i64, sum: i64) -> f64 {
+    match count {
+        0 => DEFAULT_PRIOR_MEAN,
+        count => sum as f64 / count as f64,
+    }
+}
+
+fn number(value: Option<&Bson>) -> f64 {
+    match value {
+        Some(Bson::Int32(n)) => *n as f64,
+        Some(Bson::Int64(n)) => *n as f64,
+        Some(Bson::Double(n)) => *n,
+        _ => 0.0,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counts_reviews() {
+        let mut aggregate = Aggregate::default();
+        aggregate.add(&doc! {"score": 8}, 1);
+        aggregate.add(&doc! {"score": 4.0}, 1);
+        aggregate.add(&doc! {"score": 9, "hidden": true}, 1);
+        aggregate.add(&doc! {"score": 11}, 1);
+        assert_eq!((aggregate.count, aggregate.sum), (2, 12));
+        assert_eq!(aggregate.average(), Some(6.0));
+
+        aggregate.add(&doc! {"score": 8}, -1);
+        assert_eq!((aggregate.count, aggregate.sum), (1, 4));
+        assert_eq!(aggregate.histogram[3], 1);
+        assert_eq!(aggregate.histogram[7], 0);
+    }
+
+    #[test]
+    fn never_goes_negative() {
+        let mut aggregate = Aggregate::default();
+        aggregate.add(&doc! {"score": 5}, -1);
+        assert_eq!((aggregate.count, aggregate.sum), (0, 0));
+        assert_eq!(aggregate.average(), None);
+    }
+
+    #[test]
+    fn round_trips_documents() {
+        let mut aggregate = Aggregate::default();
+        aggregate.add(&doc! {"score": 10}, 1);
+        let app = doc! {"rating": aggregate.to_document()};
+        let read = Aggregate::of(&app);
+        assert_eq!((read.count, read.sum), (1, 10));
+        assert_eq!(read.histogram[9], 1);
+        assert_eq!(
+            app.get_document("rating").unwrap().get_f64("average"),
+            Ok(10.0)
+        );
+        assert_eq!(Aggregate::of(&doc! {}).count, 0);
+    }
+
+    #[test]
+    fn builds_aggregates_from_counts() {
+        let counts = vec![
+            doc! {"app_id": "a", "score": 2, "count": 3},
+            doc! {"app_id": "a", "score": 10, "count": 1},
+            doc! {"app_id": "b", "score": 7, "count": 2},
+        ];
+        let aggregates = aggregates(&counts);
+        assert_eq!((aggregates["a"].count, aggregates["a"].sum), (4, 16));
+        assert_eq!((aggregates["b"].count, aggregates["b"].sum), (2, 14));
+    }
+
+    #[test]
+    fn weighs_towards_the_prior() {
+        assert_eq!(prior_mean(0, 0), 5.5);
+        assert_eq!(prior_mean(4, 24), 6.0);
+
+        let mut aggregate = Aggregate::default();
+        aggregate.add(&doc! {"score": 10}, 1);
+        let rating = Rating::new("a", &aggregate, 5.0);
+        assert_eq!(rating.average, Some(10.0));
+        assert_eq!(rating.weighted, Some(60.0 / 11.0));
+        assert_eq!(rating.histogram[&10], 1);
+        assert_eq!(Rating::new("b", &Aggregate::default(), 5.0).weighted, None);
+    }
+}
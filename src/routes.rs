@@ -13,10 +13,13 @@
 // You should have received a copy of the GNU General Public License
 // along with this program. If not, see <https://www.gnu.org/licenses/>.
 
+use crate::errors::CoreResult;
 use crate::types::*;
-use actix_web::{get, post, web, HttpRequest, HttpResponse, Responder};
+use actix_web::{
+    get, http::header, post, web, HttpRequest, HttpResponse, Responder, ResponseError,
+};
 use actix_web_grants::proc_macro::has_any_permission;
-use bson::Document;
+use serde::Serialize;
 
 #[post("/signup")]
 pub async fn signup(app_data: web::Data<crate::AppState>, user: web::Json<User>) -> impl Responder {
@@ -28,13 +31,64 @@ pub async fn signin(
     app_data: web::Data<crate::AppState>,
     user: web::Json<UserAuth>,
 ) -> impl Responder {
-    response(app_data.core.signin(&user.name, &user.password).await)
+    response(app_data.core.signin(&user).await)
+}
+
+#[post("/refresh")]
+pub async fn refresh(
+    app_data: web::Data<crate::AppState>,
+    info: web::Json<RefreshInfo>,
+) -> impl Responder {
+    response(app_data.core.refresh(&info).await)
+}
+
+#[post("/logout")]
+pub async fn logout(
+    app_data: web::Data<crate::AppState>,
+    info: web::Json<RefreshInfo>,
+) -> impl Responder {
+    response(app_data.core.logout(&info).await)
+}
+
+#[post("/logout_all")]
+pub async fn logout_all(
+    app_data: web::Data<crate::AppState>,
+    info: web::Json<RefreshInfo>,
+) -> impl Responder {
+    response(app_data.core.logout_all(&info).await)
+}
+
+#[get("/confirm")]
+pub async fn confirm(
+    app_data: web::Data<crate::AppState>,
+    info: web::Query<TokenInfo>,
+) -> impl Responder {
+    response(app_data.core.confirm_email(&info).await)
+}
+
+#[post("/forgot_password")]
+pub async fn forgot_password(
+    app_data: web::Data<crate::AppState>,
+    info: web::Json<ForgotPasswordInfo>,
+) -> impl Responder {
+    response(app_data.core.forgot_password(&info).await)
+}
+
+#[post("/reset_password")]
+pub async fn reset_password(
+    app_data: web::Data<crate::AppState>,
+    info: web::Json<ResetPasswordInfo>,
+) -> impl Responder {
+    response(app_data.core.reset_password(&info).await)
 }
 
 #[get("/apps")]
 #[has_any_permission("user", "admin")]
-pub async fn apps(app_data: web::Data<crate::AppState>) -> impl Responder {
-    resolve_collection(app_data.core.get_apps().await)
+pub async fn apps(
+    app_data: web::Data<crate::AppState>,
+    page: web::Query<PageQuery>,
+) -> impl Responder {
+    response(app_data.core.get_apps(&page).await)
 }
 
 #[post("/apps_by_tag")]
@@ -42,8 +96,69 @@ pub async fn apps(app_data: web::Data<crate::AppState>) -> impl Responder {
 pub async fn apps_by_tags(
     app_data: web::Data<crate::AppState>,
     info: web::Json<AppTags>,
+    page: web::Query<PageQuery>,
+) -> impl Responder {
+    response(app_data.core.get_apps_by_tag(&info, &page).await)
+}
+
+#[post("/tag_facets")]
+#[has_any_permission("user", "admin")]
+pub async fn tag_facets(
+    app_data: web::Data<crate::AppState>,
+    info: web::Json<AppTags>,
+) -> impl Responder {
+    response(app_data.core.get_tag_facets(&info).await)
+}
+
+#[get("/tags")]
+#[has_any_permission("user", "admin")]
+pub async fn tags(app_data: web::Data<crate::AppState>) -> impl Responder {
+    response(app_data.core.get_tags().await)
+}
+
+#[post("/create_tag")]
+#[has_any_permission("admin")]
+pub async fn create_tag(
+    app_data: web::Data<crate::AppState>,
+    tag: web::Json<TagData>,
+) -> impl Responder {
+    response(app_data.core.create_tag(&tag).await)
+}
+
+#[post("/edit_tag")]
+#[has_any_permission("admin")]
+pub async fn edit_tag(
+    app_data: web::Data<crate::AppState>,
+    tag: web::Json<TagData>,
+) -> impl Responder {
+    response(app_data.core.edit_tag(&tag).await)
+}
+
+#[post("/rename_tag")]
+#[has_any_permission("admin")]
+pub async fn rename_tag(
+    app_data: web::Data<crate::AppState>,
+    info: web::Json<TagChange>,
+) -> impl Responder {
+    response(app_data.core.rename_tag(&info).await)
+}
+
+#[post("/merge_tags")]
+#[has_any_permission("admin")]
+pub async fn merge_tags(
+    app_data: web::Data<crate::AppState>,
+    info: web::Json<TagChange>,
+) -> impl Responder {
+    response(app_data.core.merge_tags(&info).await)
+}
+
+#[get("/search")]
+#[has_any_permission("user", "admin")]
+pub async fn search(
+    app_data: web::Data<crate::AppState>,
+    query: web::Query<SearchQuery>,
 ) -> impl Responder {
-    resolve_collection(app_data.core.get_apps_by_tag(&info).await)
+    response(app_data.core.search(&query).await)
 }
 
 #[get("/reviews/{app_id}")]
@@ -51,8 +166,10 @@ pub async fn apps_by_tags(
 pub async fn reviews(
     app_data: web::Data<crate::AppState>,
     app_id: web::Path<String>,
+    query: web::Query<ReviewsQuery>,
+    page: web::Query<PageQuery>,
 ) -> impl Responder {
-    resolve_collection(app_data.core.get_reviews(&app_id).await)
+    response(app_data.core.get_reviews(&app_id, &query, &page).await)
 }
 
 #[get("/versions/{app_id}")]
@@ -60,8 +177,49 @@ pub async fn reviews(
 pub async fn versions(
     app_data: web::Data<crate::AppState>,
     app_id: web::Path<String>,
+    query: web::Query<VersionsQuery>,
+) -> impl Responder {
+    response(app_data.core.get_versions(&app_id, &query).await)
+}
+
+#[get("/download/{app_id}/{version}")]
+#[has_any_permission("user", "admin", "none")]
+pub async fn download(
+    app_data: web::Data<crate::AppState>,
+    path: web::Path<(String, String)>,
+    query: web::Query<VersionsQuery>,
+    req: HttpRequest,
+) -> impl Responder {
+    let (app_id, version) = path.into_inner();
+    //plain links carry no token, anonymous downloads are counted by address
+    let name = match role(req.clone()).as_str() {
+        "none" => None,
+        _ => Some(username(req.clone())),
+    };
+    let address = req
+        .connection_info()
+        .realip_remote_addr()
+        .unwrap_or_default()
+        .to_string();
+    match app_data
+        .core
+        .download(name.as_deref(), &address, &app_id, &version, &query)
+        .await
+    {
+        Ok(url) => HttpResponse::Found()
+            .append_header((header::LOCATION, url))
+            .finish(),
+        Err(e) => e.error_response(),
+    }
+}
+
+#[get("/downloads/{app_id}")]
+#[has_any_permission("user", "admin")]
+pub async fn downloads(
+    app_data: web::Data<crate::AppState>,
+    app_id: web::Path<String>,
 ) -> impl Responder {
-    resolve_collection(app_data.core.get_versions(&app_id).await)
+    response(app_data.core.get_download_stats(&app_id).await)
 }
 
 #[get("/rating/{app_id}")]
@@ -84,8 +242,18 @@ pub async fn app(app_data: web::Data<crate::AppState>, name: web::Path<String>)
 pub async fn latest_version(
     app_data: web::Data<crate::AppState>,
     name: web::Path<String>,
+    query: web::Query<VersionsQuery>,
 ) -> impl Responder {
-    response(app_data.core.get_latest_version(&name).await)
+    response(app_data.core.get_latest_version(&name, &query).await)
+}
+
+#[post("/check_updates")]
+#[has_any_permission("user", "admin")]
+pub async fn check_updates(
+    app_data: web::Data<crate::AppState>,
+    info: web::Json<UpdatesInfo>,
+) -> impl Responder {
+    response(app_data.core.check_updates(&info).await)
 }
 
 #[get("/personal_library")]
@@ -104,12 +272,16 @@ pub async fn change_password(
     info: web::Json<PasswordsInf>,
     req: HttpRequest,
 ) -> impl Responder {
-    response(
-        app_data
-            .core
-            .change_password(&username(req), &info.old_password, &info.new_password)
-            .await,
-    )
+    response(app_data.core.change_password(&username(req), &info).await)
+}
+
+#[post("/resend_confirmation")]
+#[has_any_permission("user", "admin")]
+pub async fn resend_confirmation(
+    app_data: web::Data<crate::AppState>,
+    req: HttpRequest,
+) -> impl Responder {
+    response(app_data.core.resend_confirmation(&username(req)).await)
 }
 
 #[post("/update")]
@@ -172,6 +344,200 @@ pub async fn write_review(
     )
 }
 
+#[post("/delete_review")]
+#[has_any_permission("user", "admin")]
+pub async fn delete_review(
+    app_data: web::Data<crate::AppState>,
+    app_info: web::Json<AppInfo>,
+    req: HttpRequest,
+) -> impl Responder {
+    response(
+        app_data
+            .core
+            .delete_review(&username(req), &app_info.app_id)
+            .await,
+    )
+}
+
+#[post("/vote_review")]
+#[has_any_permission("user", "admin")]
+pub async fn vote_review(
+    app_data: web::Data<crate::AppState>,
+    vote: web::Json<ReviewVote>,
+    req: HttpRequest,
+) -> impl Responder {
+    response(app_data.core.vote_review(&username(req), &vote).await)
+}
+
+#[get("/my_reviews")]
+#[has_any_permission("user", "admin")]
+pub async fn my_reviews(app_data: web::Data<crate::AppState>, req: HttpRequest) -> impl Responder {
+    response(app_data.core.get_user_reviews(&username(req)).await)
+}
+
+#[post("/reply_review")]
+#[has_any_permission("developer", "admin")]
+pub async fn reply_review(
+    app_data: web::Data<crate::AppState>,
+    reply: web::Json<ReviewReply>,
+    req: HttpRequest,
+) -> impl Responder {
+    response(
+        app_data
+            .core
+            .reply_review(&username(req.clone()), &role(req), &reply)
+            .await,
+    )
+}
+
+#[post("/delete_reply")]
+#[has_any_permission("developer", "admin")]
+pub async fn delete_reply(
+    app_data: web::Data<crate::AppState>,
+    review: web::Json<ReviewAuthor>,
+    req: HttpRequest,
+) -> impl Responder {
+    response(
+        app_data
+            .core
+            .delete_reply(&username(req.clone()), &role(req), &review)
+            .await,
+    )
+}
+
+#[post("/report_review")]
+#[has_any_permission("user", "admin")]
+pub async fn report_review(
+    app_data: web::Data<crate::AppState>,
+    report: web::Json<ReviewReport>,
+    req: HttpRequest,
+) -> impl Responder {
+    response(app_data.core.report_review(&username(req), &report).await)
+}
+
+#[get("/moderation_queue")]
+#[has_any_permission("admin")]
+pub async fn moderation_queue(
+    app_data: web::Data<crate::AppState>,
+    page: web::Query<PageQuery>,
+) -> impl Responder {
+    response(app_data.core.moderation_queue(&page).await)
+}
+
+#[post("/moderate_review")]
+#[has_any_permission("admin")]
+pub async fn moderate_review(
+    app_data: web::Data<crate::AppState>,
+    info: web::Json<ModerationInfo>,
+    req: HttpRequest,
+) -> impl Responder {
+    response(app_data.core.moderate_review(&username(req), &info).await)
+}
+
+#[get("/review_history/{app_id}/{user_name}")]
+#[has_any_permission("admin")]
+pub async fn review_history(
+    app_data: web::Data<crate::AppState>,
+    path: web::Path<(String, String)>,
+) -> impl Responder {
+    let (app_id, user_name) = path.into_inner();
+    response(app_data.core.get_review_history(&app_id, &user_name).await)
+}
+
+#[post("/become_developer")]
+#[has_any_permission("user", "admin")]
+pub async fn become_developer(
+    app_data: web::Data<crate::AppState>,
+    req: HttpRequest,
+) -> impl Responder {
+    response(app_data.core.become_developer(&username(req)).await)
+}
+
+#[get("/developer_apps")]
+#[has_any_permission("developer", "admin")]
+pub async fn developer_apps(
+    app_data: web::Data<crate::AppState>,
+    req: HttpRequest,
+    page: web::Query<PageQuery>,
+) -> impl Responder {
+    response(
+        app_data
+            .core
+            .get_developer_apps(&username(req), &page)
+            .await,
+    )
+}
+
+#[post("/create_app")]
+#[has_any_permission("developer", "admin")]
+pub async fn create_app(
+    app_data: web::Data<crate::AppState>,
+    app_info: web::Json<AppData>,
+    req: HttpRequest,
+) -> impl Responder {
+    response(app_data.core.create_app(&username(req), &app_info).await)
+}
+
+#[post("/edit_app")]
+#[has_any_permission("developer", "admin")]
+pub async fn edit_app(
+    app_data: web::Data<crate::AppState>,
+    app_info: web::Json<AppData>,
+    req: HttpRequest,
+) -> impl Responder {
+    response(
+        app_data
+            .core
+            .edit_app(&username(req.clone()), &role(req), &app_info)
+            .await,
+    )
+}
+
+#[post("/unpublish_app")]
+#[has_any_permission("developer", "admin")]
+pub async fn unpublish_app(
+    app_data: web::Data<crate::AppState>,
+    app_info: web::Json<AppInfo>,
+    req: HttpRequest,
+) -> impl Responder {
+    response(
+        app_data
+            .core
+            .set_app_published(&username(req.clone()), &role(req), &app_info.app_id, false)
+            .await,
+    )
+}
+
+#[post("/publish_app")]
+#[has_any_permission("developer", "admin")]
+pub async fn publish_app(
+    app_data: web::Data<crate::AppState>,
+    app_info: web::Json<AppInfo>,
+    req: HttpRequest,
+) -> impl Responder {
+    response(
+        app_data
+            .core
+            .set_app_published(&username(req.clone()), &role(req), &app_info.app_id, true)
+            .await,
+    )
+}
+
+#[post("/publish_version")]
+#[has_any_permission("developer", "admin")]
+pub async fn publish_version(
+    app_data: web::Data<crate::AppState>,
+    version: web::Json<VersionData>,
+    req: HttpRequest,
+) -> impl Responder {
+    response(
+        app_data
+            .core
+            .publish_version(&username(req.clone()), &role(req), &version)
+            .await,
+    )
+}
+
 fn username(req: HttpRequest) -> String {
     req.headers()
         .get("osma-username")
@@ -182,23 +548,18 @@ fn username(req: HttpRequest) -> String {
         .to_string()
 }
 
-fn resolve_collection(resp: Result<Vec<Document>, serde_json::Value>) -> HttpResponse {
-    match resp {
-        Ok(resp) => HttpResponse::Ok().json(resp),
-        Err(e) => HttpResponse::InternalServerError().json(e),
-    }
+fn role(req: HttpRequest) -> String {
+    req.headers()
+        .get("osma-role")
+        .unwrap()
+        .to_str()
+        .ok()
+        .unwrap()
+        .to_string()
 }
 
-fn response(result: serde_json::Value) -> impl Responder {
-    if result["code"] == "ok" {
-        HttpResponse::Ok().json(result)
-    } else if result["code"] == "ok_body" {
-        HttpResponse::Ok().json(&result["body"])
-    } else if result["code"] == "denied" {
-        HttpResponse::Forbidden().json(result)
-    } else {
-        HttpResponse::InternalServerError().json(result)
-    }
+fn response<T: Serialize>(result: CoreResult<T>) -> impl Responder {
+    result.map(|body| HttpResponse::Ok().json(body))
 }
 
 //req: HttpRequest
@@ -0,0 +1,393 @@
+// Copyright (c) 2023 artegoser (Artemy Egorov)
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+use bson::{Bson, Document};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::fs;
+
+use crate::search::tokenize;
+
+/// Tunable limits of the checks, loaded from a json file with the same layout
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct Rules {
+    /// Word lists by language, every language is checked
+    pub languages: BTreeMap<String, WordList>,
+    /// Links allowed before the review is held
+    pub max_links: usize,
+    /// Longest run of one character, longer runs are rejected
+    pub max_repeated_chars: usize,
+    /// Shorter texts are not checked for duplicates
+    pub min_duplicate_chars: usize,
+    /// Reviews a user can write or edit in `rate_window` seconds
+    pub max_reviews: usize,
+    pub rate_window: i64,
+}
+
+impl Default for Rules {
+    fn default() -> Rules {
+        Rules {
+            languages: BTreeMap::new(),
+            max_links: 2,
+            max_repeated_chars: 10,
+            min_duplicate_chars: 30,
+            max_reviews: 5,
+            rate_window: 3600,
+        }
+    }
+}
+
+impl Rules {
+    pub fn from_file(path: &str) -> Result<Rules, Box<dyn std::error::Error>> {
+        Ok(serde_json::from_str(&fs::read_to_string(path)?)?)
+    }
+}
+
+/// Words and phrases, matched as whole words ignoring case
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct WordList {
+    pub reject: Vec<String>,
+    pub hold: Vec<String>,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Verdict {
+    Accept,
+    /// Stored hidden until a moderator restores it
+    Hold(String),
+    Reject(String),
+}
+
+/// A review about to be stored
+pub struct Submission<'a> {
+    pub text: &'a str,
+    /// Other reviews of the same user
+    pub others: &'a [Document],
+    /// Earlier writes of the user with their `timestamp`, reviews and revisions of any app
+    pub writes: &'a [Document],
+    pub now: i64,
+}
+
+pub trait Check: Send + Sync {
+    fn check(&self, submission: &Submission) -> Verdict;
+}
+
+/// Runs every check, a rejection wins over a hold
+pub struct Screening {
+    checks: Vec<Box<dyn Check>>,
+    history: i64,
+}
+
+impl Screening {
+    /// The built in checks configured by the rules
+    pub fn new(rules: Rules) -> Screening {
+        let mut reject = vec![];
+        let mut hold = vec![];
+        for list in rules.languages.into_values() {
+            reject.extend(list.reject);
+            hold.extend(list.hold);
+        }
+        Screening {
+            checks: vec![],
+            history: rules.rate_window,
+        }
+        .with(Words {
+            phrases: phrases(&reject),
+            verdict: || Verdict::Reject("The review contains prohibited words".to_string()),
+        })
+        .with(Words {
+            phrases: phrases(&hold),
+            verdict: || Verdict::Hold("The review contains suspicious words".to_string()),
+        })
+        .with(Links {
+            max: rules.max_links,
+        })
+        .with(RepeatedChars {
+            max: rules.max_repeated_chars,
+        })
+        .with(Duplicates {
+            min_chars: rules.min_duplicate_chars,
+        })
+        .with(Rate {
+            max: rules.max_reviews,
+            window: rules.rate_window,
+        })
+    }
+
+    /// Seconds of earlier writes the checks need in `Submission::writes`
+    pub fn history(&self) -> i64 {
+        self.history
+    }
+
+    pub fn with(mut self, check: impl Check + 'static) -> Screening {
+        self.checks.push(Box::new(check));
+        self
+    }
+
+    pub fn screen(&self, submission: &Submission) -> Verdict {
+        let mut verdict = Verdict::Accept;
+        for check in self.checks.iter() {
+            match check.check(submission) {
+                Verdict::Accept => {}
+                Verdict::Reject(reason) => return Verdict::Reject(reason),
+                Verdict::Hold(reason) => {
+                    if verdict == Verdict::Accept {
+                        verdict = Verdict::Hold(reason)
+                    }
+                }
+            }
+        }
+        verdict
+    }
+}
+
+fn phrases(list: &[String]) -> Vec<Vec<String>> {
+    list.iter()
+        .map(|phrase| tokenize(phrase))
+        .filter(|words| !words.is_empty())
+        .collect()
+}
+
+struct Words {
+    phrases: Vec<Vec<String>>,
+    verdict: fn() -> Verdict,
+}
+
+impl Check for Words {
+    fn check(&self, submission: &Submission) -> Verdict {
+        let words = tokenize(submission.text);
+        let found = self.phrases.iter().any(|phrase| {
+            words
+                .windows(phrase.len())
+                .any(|window| window == phrase.as_slice())
+        });
+        match found {
+            true => (self.verdict)(),
+            false => Verdict::Accept,
+        }
+    }
+}
+
+struct Links {
+    max: usize,
+}
+
+impl Check for Links {
+    fn check(&self, submission: &Submission) -> Verdict {
+        let links = submission
+            .text
+            .split_whitespace()
+            .map(|word| word.to_lowercase())
+            .filter(|word| {
+                word.contains("http://") || word.contains("https://") || word.starts_with("www.")
+            })
+            .count();
+        match links > self.max {
+            true => Verdict::Hold(format!("The review has more than {} links", self.max)),
+            false => Verdict::Accept,
+        }
+    }
+}
+
+struct RepeatedChars {
+    max: usize,
+}
+
+impl Check for RepeatedChars {
+    fn check(&self, submission: &Submission) -> Verdict {
+        let mut run = 0;
+        let mut last = None;
+        for c in submission.text.chars() {
+            run = if last == Some(c) { run + 1 } else { 1 };
+            last = Some(c);
+            if run > self.max && !c.is_whitespace() {
+                return Verdict::Reject(format!(
+                    "The review repeats a character more than {} times",
+                    self.max
+                ));
+            }
+        }
+        Verdict::Accept
+    }
+}
+
+struct Duplicates {
+    min_chars: usize,
+}
+
+impl Check for Duplicates {
+    fn check(&self, submission: &Submission) -> Verdict {
+        let text = tokenize(submission.text).join(" ");
+        if text.chars().count() < self.min_chars {
+            return Verdict::Accept;
+        }
+        let duplicate = submission
+            .others
+            .iter()
+            .any(|review| tokenize(review.get_str("text").unwrap_or_default()).join(" ") == text);
+        match duplicate {
+            true => Verdict::Hold("The same text is in another review".to_string()),
+            false => Verdict::Accept,
+        }
+    }
+}
+
+struct Rate {
+    max: usize,
+    window: i64,
+}
+
+impl Check for Rate {
+    fn check(&self, submission: &Submission) -> Verdict {
+        let since = submission.now - self.window;
+        let recent = submission
+            .writes
+            .iter()
+            .filter(|review| timestamp(review) >= since)
+            .count();
+        match recent >= self.max {
+            true => Verdict::Reject("Too many reviews, try again later".to_string()),
+            false => Verdict::Accept,
+        }
+    }
+}
+
+fn timestamp(review: &Document) -> i64 {
+    match review.get("timestamp") {
+        Some(Bson::Int64(timestamp)) => *timestamp,
+        Some(Bson::Int32(timestamp)) => *timestamp as i64,
+        Some(Bson::Double(timestamp)) => *timestamp as i64,
+        _ => 0,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bson::doc;
+
+    fn screening() -> Screening {
+        let mut rules = Rules::default();
+        rules.languages.insert(
+            "en".to_string(),
+            WordList {
+                reject: vec!["Buy Now".to_string()],
+                hold: vec!["casino".to_string()],
+            },
+        );
+        Screening::new(rules)
+    }
+
+    fn screen(text: &str, others: &[Document], writes: &[Document]) -> Verdict {
+        screening().screen(&Submission {
+            text,
+            others,
+            writes,
+            now: 10_000,
+        })
+    }
+
+    fn is_hold(verdict: Verdict) -> bool {
+        matches!(verdict, Verdict::Hold(_))
+    }
+
+    fn is_reject(verdict: Verdict) -> bool {
+        matches!(verdict, Verdict::Reject(_))
+    }
+
+    #[test]
+    fn accepts_plain_reviews() {
+        assert_eq!(screen("Works well, nice UI", &[], &[]), Verdict::Accept);
+    }
+
+    #[test]
+    fn matches_whole_words() {
+        assert!(is_reject(screen("buy, NOW!", &[], &[])));
+        assert!(is_hold(screen("Better than the Casino app", &[], &[])));
+        assert_eq!(screen("buy it now, casinos", &[], &[]), Verdict::Accept);
+    }
+
+    #[test]
+    fn rejections_win_over_holds() {
+        assert!(is_reject(screen("casino: buy now", &[], &[])));
+    }
+
+    #[test]
+    fn holds_links() {
+        assert_eq!(
+            screen("see https://a.org and www.b.org", &[], &[]),
+            Verdict::Accept
+        );
+        assert!(is_hold(screen(
+            "see https://a.org, www.b.org and HTTP://c.org",
+            &[],
+            &[]
+        )));
+    }
+
+    #[test]
+    fn rejects_repeated_chars() {
+        assert!(is_reject(screen("Great!!!!!!!!!!!", &[], &[])));
+        assert_eq!(
+            screen(&format!("Great{}app", " ".repeat(20)), &[], &[]),
+            Verdict::Accept
+        );
+    }
+
+    #[test]
+    fn holds_duplicates() {
+        let text = "This app is the best editor I have ever used";
+        let others = [doc! {"text": "this app is the best editor, i have ever used!"}];
+        assert!(is_hold(screen(text, &others, &[])));
+        let short = [doc! {"text": "Great app"}];
+        assert_eq!(screen("Great app", &short, &[]), Verdict::Accept);
+    }
+
+    #[test]
+    fn limits_the_rate() {
+        let writes: Vec<Document> = (0..5).map(|i| doc! {"timestamp": 9_000 + i}).collect();
+        assert!(is_reject(screen("Fine", &[], &writes)));
+        assert_eq!(screen("Fine", &[], &writes[1..]), Verdict::Accept);
+        let old: Vec<Document> = (0..5).map(|i| doc! {"timestamp": 1_000 + i}).collect();
+        assert_eq!(screen("Fine", &[], &old), Verdict::Accept);
+        assert_eq!(screening().history(), 3600);
+    }
+
+    #[test]
+    fn runs_extra_checks() {
+        struct Shouting;
+        impl Check for Shouting {
+            fn check(&self, submission: &Submission) -> Verdict {
+                match submission.text.chars().any(char::is_lowercase) {
+                    true => Verdict::Accept,
+                    false => Verdict::Hold("Shouting".to_string()),
+                }
+            }
+        }
+        let screening = screening().with(Shouting);
+        let submission = Submission {
+            text: "GREAT APP",
+            others: &[],
+            writes: &[],
+            now: 0,
+        };
+        assert_eq!(
+            screening.screen(&submission),
+            Verdict::Hold("Shouting".to_string())
+        );
+    }
+}
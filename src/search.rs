@@ -0,0 +1,272 @@
+// Copyright (c) 2023 artegoser (Artemy Egorov)
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+use bson::{doc, Bson, Document, Regex};
+use std::collections::{BTreeMap, HashMap};
+
+/// Searched fields and how much a match in them is worth
+const FIELDS: [(&str, f64); 4] = [
+    ("name", 10.0),
+    ("tags", 5.0),
+    ("author", 3.0),
+    ("description", 1.0),
+];
+
+const EXACT_MATCH: f64 = 1.0;
+const PREFIX_MATCH: f64 = 0.8;
+/// Score of a match with one typo, halved for each further typo
+const TYPO_MATCH: f64 = 0.6;
+
+/// Words to search for, every one of them must match
+pub struct Query {
+    terms: Vec<String>,
+    /// The last term may be incomplete while the user is typing
+    prefix: bool,
+}
+
+impl Query {
+    pub fn parse(text: &str) -> Query {
+        let mut terms: Vec<String> = vec![];
+        for term in tokenize(text) {
+            if !terms.contains(&term) {
+                terms.push(term);
+            }
+        }
+        Query {
+            terms,
+            prefix: !text.ends_with(char::is_whitespace),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.terms.is_empty()
+    }
+
+    /// Mongodb filter that matches at least every app the query can match.
+    ///
+    /// A word within `n` typos of a term contains one of `n + 1` parts of the term unchanged.
+    pub fn candidate_filter(&self) -> Document {
+        let terms: Vec<Document> = self
+            .terms
+            .iter()
+            .map(|term| {
+                let pattern = split(term, allowed_typos(term) + 1).join("|");
+                let fields: Vec<Document> = FIELDS
+                    .iter()
+                    .map(|(field, _)| {
+                        doc! {*field: Regex { pattern: pattern.clone(), options: "i".to_string() }}
+                    })
+                    .collect();
+                doc! {"$or": fields}
+            })
+            .collect();
+        doc! {"$and": terms}
+    }
+
+    fn is_last(&self, i: usize) -> bool {
+        i + 1 == self.terms.len()
+    }
+}
+
+/// Inverted index of the searched fields of apps
+#[derive(Default)]
+pub struct Index {
+    /// Word to the (app, field) pairs it occurs in
+    words: BTreeMap<String, Vec<(usize, usize)>>,
+}
+
+impl Index {
+    pub fn new(apps: &[Document]) -> Index {
+        let mut words: BTreeMap<String, Vec<(usize, usize)>> = BTreeMap::new();
+        for (app, document) in apps.iter().enumerate() {
+            for (field, (name, _)) in FIELDS.iter().enumerate() {
+                for word in field_words(document, name) {
+                    let postings = words.entry(word).or_default();
+                    if !postings.contains(&(app, field)) {
+                        postings.push((app, field));
+                    }
+                }
+            }
+        }
+        Index { words }
+    }
+
+    /// Positions of the matching apps with their relevance, most relevant first
+    pub fn search(&self, query: &Query) -> Vec<(usize, f64)> {
+        let mut relevance: HashMap<usize, f64> = HashMap::new();
+        for (i, term) in query.terms.iter().enumerate() {
+            let mut term_relevance: HashMap<usize, f64> = HashMap::new();
+            for (word, score) in self.matches(term, query.prefix && query.is_last(i)) {
+                for (app, field) in &self.words[word] {
+                    let score = score * FIELDS[*field].1;
+                    let best = term_relevance.entry(*app).or_default();
+                    *best = best.max(score);
+                }
+            }
+
+            if i == 0 {
+                relevance = term_relevance;
+            } else {
+                relevance.retain(|app, _| term_relevance.contains_key(app));
+                for (app, score) in relevance.iter_mut() {
+                    *score += term_relevance[app];
+                }
+            }
+        }
+
+        let mut hits: Vec<(usize, f64)> = relevance.into_iter().collect();
+        hits.sort_by(|a, b| b.1.total_cmp(&a.1).then(a.0.cmp(&b.0)));
+        hits
+    }
+
+    /// Indexed words matching the term and how well they do
+    fn matches(&self, term: &str, prefix: bool) -> Vec<(&String, f64)> {
+        let max_typos = allowed_typos(term);
+        let mut matches = vec![];
+        for word in self.words.keys() {
+            if word == term {
+                matches.push((word, EXACT_MATCH));
+            } else if prefix && word.starts_with(term) {
+                matches.push((word, PREFIX_MATCH));
+            } else if max_typos > 0 {
+                if let Some(typos) = distance(term, word, max_typos) {
+                    matches.push((word, TYPO_MATCH / 2f64.powi(typos as i32 - 1)));
+                }
+            }
+        }
+        matches
+    }
+}
+
+/// Lowercase words of the text
+pub fn tokenize(text: &str) -> Vec<String> {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(|word| word.to_lowercase())
+        .collect()
+}
+
+fn field_words(document: &Document, field: &str) -> Vec<String> {
+    match document.get(field) {
+        Some(Bson::String(text)) => tokenize(text),
+        Some(Bson::Array(values)) => values
+            .iter()
+            .filter_map(|value| value.as_str())
+            .flat_map(tokenize)
+            .collect(),
+        _ => vec![],
+    }
+}
+
+/// Short words must be exact, longer ones can have more typos
+fn allowed_typos(term: &str) -> usize {
+    match term.chars().count() {
+        0..=3 => 0,
+        4..=7 => 1,
+        _ => 2,
+    }
+}
+
+fn split(term: &str, parts: usize) -> Vec<String> {
+    let chars: Vec<char> = term.chars().collect();
+    let size = chars.len().div_ceil(parts);
+    chars
+        .chunks(size)
+        .map(|part| part.iter().collect())
+        .collect()
+}
+
+/// Levenshtein distance of the words if it is at most `max`
+fn distance(a: &str, b: &str, max: usize) -> Option<usize> {
+    let a: Vec<char> = a.chars().collect();
+    let b: Vec<char> = b.chars().collect();
+    if a.len().abs_diff(b.len()) > max {
+        return None;
+    }
+
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.iter().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(ca != cb);
+            current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        if current.iter().min() > Some(&max) {
+            return None;
+        }
+        previous = current;
+    }
+
+    Some(previous[b.len()]).filter(|distance| *distance <= max)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn apps() -> Vec<Document> {
+        vec![
+            doc! {"name": "Firefox", "tags": ["browser"], "author": "mozilla", "description": "Web browser"},
+            doc! {"name": "Thunderbird", "tags": ["mail"], "author": "mozilla", "description": "Mail client with a browser view"},
+            doc! {"name": "Gimp", "tags": ["graphics"], "author": "gnome", "description": "Image editor"},
+        ]
+    }
+
+    #[test]
+    fn parses_queries() {
+        let query = Query::parse("Web, web browser ");
+        assert_eq!(query.terms, ["web", "browser"]);
+        assert!(!query.prefix);
+        assert!(Query::parse("fire").prefix);
+        assert!(Query::parse(" -- ").is_empty());
+    }
+
+    #[test]
+    fn ranks_by_field() {
+        let index = Index::new(&apps());
+        let hits = index.search(&Query::parse("browser "));
+        assert_eq!(hits.iter().map(|h| h.0).collect::<Vec<_>>(), [0, 1]);
+        assert!(hits[0].1 > hits[1].1);
+    }
+
+    #[test]
+    fn matches_prefixes_and_typos() {
+        let index = Index::new(&apps());
+        assert_eq!(index.search(&Query::parse("thund"))[0].0, 1);
+        assert!(index.search(&Query::parse("thund ")).is_empty());
+        assert_eq!(index.search(&Query::parse("firefux "))[0].0, 0);
+        assert!(index.search(&Query::parse("gomq ")).is_empty());
+    }
+
+    #[test]
+    fn requires_every_term() {
+        let index = Index::new(&apps());
+        let hits = index.search(&Query::parse("mozilla mail"));
+        assert_eq!(hits.iter().map(|h| h.0).collect::<Vec<_>>(), [1]);
+    }
+
+    #[test]
+    fn measures_distance() {
+        assert_eq!(distance("kitten", "sitting", 3), Some(3));
+        assert_eq!(distance("kitten", "sitting", 2), None);
+        assert_eq!(distance("mail", "mail", 0), Some(0));
+    }
+
+    #[test]
+    fn splits_terms() {
+        assert_eq!(split("browser", 3), ["bro", "wse", "r"]);
+        assert_eq!(split("gimp", 1), ["gimp"]);
+    }
+}
@@ -0,0 +1,885 @@
+// Copyright (c) 2023 artegoser (Artemy Egorov)
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+use async_trait::async_trait;
+use bson::{doc, Bson, Document};
+use serde::Deserialize;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::RwLock;
+
+use super::{vote_tallies, ReviewFilter, Storage, StorageError, StorageResult, TagFilter};
+use crate::pagination::{Page, PageRequest};
+use crate::rating::Aggregate;
+use crate::search;
+
+/// Collections kept in memory, can be seeded from a json file with the same layout
+#[derive(Default, Deserialize)]
+#[serde(default)]
+struct Collections {
+    users: Vec<Document>,
+    apps: Vec<Document>,
+    apps_versions: Vec<Document>,
+    reviews: Vec<Document>,
+    review_revisions: Vec<Document>,
+    review_votes: Vec<Document>,
+    review_reports: Vec<Document>,
+    personal_libraries: Vec<Document>,
+    sessions: Vec<Document>,
+    tags: Vec<Document>,
+    downloads: Vec<Document>,
+    #[serde(skip)]
+    search_index: search::Index,
+    /// `count` and `sum` of the `rating` of all apps added up
+    #[serde(skip)]
+    rating_totals: (i64, i64),
+}
+
+impl Collections {
+    /// Must be called after every change of `apps`
+    fn reindex(&mut self) {
+        self.search_index = search::Index::new(&self.apps);
+    }
+
+    /// Seeded reviews without votes start with empty tallies
+    fn fill_tallies(&mut self) {
+        for review in self.reviews.iter_mut() {
+            for tally in ["helpful", "unhelpful"] {
+                if !review.contains_key(tally) {
+                    review.insert(tally, 0);
+                }
+            }
+        }
+    }
+
+    /// Seeded apps get their `rating` from the seeded reviews
+    fn recount_ratings(&mut self) {
+        for app in self.apps.iter_mut() {
+            let app_id = app.get_str("app_id").unwrap_or_default().to_string();
+            let mut rating = Aggregate::default();
+            for review in self.reviews.iter().filter(|r| str_eq(r, "app_id", &app_id)) {
+                rating.add(review, 1);
+            }
+            app.insert("rating", rating.to_document());
+        }
+        self.count_rating_totals();
+    }
+
+    fn count_rating_totals(&mut self) {
+        self.rating_totals = self
+            .apps
+            .iter()
+            .map(Aggregate::of)
+            .fold((0, 0), |(count, sum), rating| {
+                (count + rating.count, sum + rating.sum)
+            });
+    }
+
+    /// Updates the `rating` of the app and the totals for a replaced or deleted review
+    fn change_rating(
+        &mut self,
+        app_id: &str,
+        previous: Option<&Document>,
+        review: Option<&Document>,
+    ) {
+        if let Some(app) = find_mut(&mut self.apps, "app_id", app_id) {
+            let mut rating = Aggregate::of(app);
+            let (count, sum) = (rating.count, rating.sum);
+            if let Some(previous) = previous {
+                rating.add(previous, -1);
+            }
+            if let Some(review) = review {
+                rating.add(review, 1);
+            }
+            app.insert("rating", rating.to_document());
+            self.rating_totals.0 += rating.count - count;
+            self.rating_totals.1 += rating.sum - sum;
+        }
+    }
+}
+
+/// Storage without a database, everything is lost on restart
+#[derive(Default)]
+pub struct MemoryStorage {
+    data: RwLock<Collections>,
+}
+
+impl MemoryStorage {
+    pub fn new() -> MemoryStorage {
+        MemoryStorage::default()
+    }
+
+    pub fn from_seed(path: &str) -> std::io::Result<MemoryStorage> {
+        let file = std::fs::File::open(path)?;
+        let mut data: Collections = serde_json::from_reader(file)?;
+        data.fill_tallies();
+        data.recount_ratings();
+        data.reindex();
+        Ok(MemoryStorage {
+            data: RwLock::new(data),
+        })
+    }
+
+    fn read<T>(&self, f: impl FnOnce(&Collections) -> T) -> StorageResult<T> {
+        match self.data.read() {
+            Ok(data) => Ok(f(&data)),
+            Err(_) => Err(poisoned()),
+        }
+    }
+
+    fn write<T>(&self, f: impl FnOnce(&mut Collections) -> StorageResult<T>) -> StorageResult<T> {
+        match self.data.write() {
+            Ok(mut data) => f(&mut data),
+            Err(_) => Err(poisoned()),
+        }
+    }
+}
+
+fn poisoned() -> StorageError {
+    StorageError::Backend("memory storage lock is poisoned".into())
+}
+
+fn str_eq(document: &Document, key: &str, value: &str) -> bool {
+    document.get_str(key).ok() == Some(value)
+}
+
+fn number(document: &Document, key: &str) -> f64 {
+    match document.get(key) {
+        Some(Bson::Int32(n)) => *n as f64,
+        Some(Bson::Int64(n)) => *n as f64,
+        Some(Bson::Double(n)) => *n,
+        _ => 0.0,
+    }
+}
+
+fn published(app: &Document) -> bool {
+    app.get_bool("published").unwrap_or(true)
+}
+
+fn hidden(review: &Document) -> bool {
+    review.get_bool("hidden").unwrap_or(false)
+}
+
+fn app_tags(app: &Document) -> Vec<&str> {
+    match app.get_array("tags") {
+        Ok(tags) => tags.iter().filter_map(|tag| tag.as_str()).collect(),
+        Err(_) => vec![],
+    }
+}
+
+fn has_tags(app: &Document, filter: &TagFilter) -> bool {
+    let tags = app_tags(app);
+    let has_any = |group: &Vec<String>| group.iter().any(|tag| tags.contains(&tag.as_str()));
+    (filter.any.is_empty() || has_any(&filter.any))
+        && filter.all.iter().all(has_any)
+        && !has_any(&filter.exclude)
+}
+
+fn without_id(document: &Document) -> Document {
+    let mut document = document.clone();
+    document.remove("_id");
+    document
+}
+
+fn find<'a>(documents: &'a [Document], key: &str, value: &str) -> Option<&'a Document> {
+    documents.iter().find(|d| str_eq(d, key, value))
+}
+
+fn find_mut<'a>(documents: &'a mut [Document], key: &str, value: &str) -> Option<&'a mut Document> {
+    documents.iter_mut().find(|d| str_eq(d, key, value))
+}
+
+fn library_apps(library: &mut Document) -> &mut Vec<Bson> {
+    if library.get_array("apps").is_err() {
+        library.insert("apps", Bson::Array(vec![]));
+    }
+    library.get_array_mut("apps").unwrap()
+}
+
+#[async_trait]
+impl Storage for MemoryStorage {
+    /// Nothing to migrate, documents are not validated in memory
+    async fn migrate(&self, _apply: bool) -> StorageResult<Vec<String>> {
+        Ok(vec![])
+    }
+
+    async fn find_user(&self, name: &str) -> StorageResult<Option<Document>> {
+        self.read(|data| find(&data.users, "name", name).cloned())
+    }
+
+    async fn find_user_by(&self, field: &str, value: &str) -> StorageResult<Option<Document>> {
+        self.read(|data| find(&data.users, field, value).cloned())
+    }
+
+    async fn insert_user(&self, user: Document) -> StorageResult<()> {
+        self.write(|data| {
+            let name = user.get_str("name").unwrap_or_default();
+            if find(&data.users, "name", name).is_some() {
+                return Err(StorageError::Duplicate);
+            }
+            data.users.push(user);
+            Ok(())
+        })
+    }
+
+    async fn update_user(&self, name: &str, fields: Document) -> StorageResult<()> {
+        self.write(|data| {
+            if let Some(user) = find_mut(&mut data.users, "name", name) {
+                user.extend(fields);
+            }
+            Ok(())
+        })
+    }
+
+    async fn get_apps(&self, page: &PageRequest) -> StorageResult<Page> {
+        self.read(|data| {
+            page.paginate(
+                data.apps
+                    .iter()
+                    .filter(|app| published(app))
+                    .map(without_id)
+                    .collect(),
+            )
+        })
+    }
+
+    async fn get_apps_by_tags(&self, tags: &TagFilter, page: &PageRequest) -> StorageResult<Page> {
+        self.read(|data| {
+            page.paginate(
+                data.apps
+                    .iter()
+                    .filter(|app| published(app) && has_tags(app, tags))
+                    .map(without_id)
+                    .collect(),
+            )
+        })
+    }
+
+    async fn get_tag_counts(&self, tags: &TagFilter) -> StorageResult<Vec<Document>> {
+        self.read(|data| {
+            let mut counts: BTreeMap<&str, i32> = BTreeMap::new();
+            for app in data
+                .apps
+                .iter()
+                .filter(|app| published(app) && has_tags(app, tags))
+            {
+                for tag in app_tags(app) {
+                    *counts.entry(tag).or_default() += 1;
+                }
+            }
+
+            let mut counts: Vec<(&str, i32)> = counts.into_iter().collect();
+            counts.sort_by_key(|c| std::cmp::Reverse(c.1));
+            counts
+                .into_iter()
+                .map(|(tag, count)| doc! {"tag": tag, "count": count})
+                .collect()
+        })
+    }
+
+    async fn replace_app_tag(&self, from: &str, to: &str) -> StorageResult<()> {
+        self.write(|data| {
+            for app in data.apps.iter_mut() {
+                let tags = match app.get_array_mut("tags") {
+                    Ok(tags) => tags,
+                    Err(_) => continue,
+                };
+                if tags.iter().any(|tag| tag.as_str() == Some(to)) {
+                    tags.retain(|tag| tag.as_str() != Some(from));
+                } else {
+                    for tag in tags.iter_mut().filter(|tag| tag.as_str() == Some(from)) {
+                        *tag = Bson::String(to.to_string());
+                    }
+                }
+            }
+            data.reindex();
+            Ok(())
+        })
+    }
+
+    async fn find_app(&self, app_id: &str) -> StorageResult<Option<Document>> {
+        self.read(|data| find(&data.apps, "app_id", app_id).cloned())
+    }
+
+    async fn get_apps_by_author(&self, author: &str, page: &PageRequest) -> StorageResult<Page> {
+        self.read(|data| {
+            page.paginate(
+                data.apps
+                    .iter()
+                    .filter(|app| str_eq(app, "author", author))
+                    .map(without_id)
+                    .collect(),
+            )
+        })
+    }
+
+    async fn insert_app(&self, app: Document) -> StorageResult<()> {
+        self.write(|data| {
+            let app_id = app.get_str("app_id").unwrap_or_default();
+            if find(&data.apps, "app_id", app_id).is_some() {
+                return Err(StorageError::Duplicate);
+            }
+            data.apps.push(app);
+            data.reindex();
+            Ok(())
+        })
+    }
+
+    async fn update_app(&self, app_id: &str, fields: Document) -> StorageResult<()> {
+        self.write(|data| {
+            if let Some(app) = find_mut(&mut data.apps, "app_id", app_id) {
+                app.extend(fields);
+            }
+            data.reindex();
+            Ok(())
+        })
+    }
+
+    async fn get_rating_totals(&self) -> StorageResult<(i64, i64)> {
+        self.read(|data| data.rating_totals)
+    }
+
+    async fn set_ratings(&self, mut ratings: BTreeMap<String, Document>) -> StorageResult<()> {
+        self.write(|data| {
+            for app in data.apps.iter_mut() {
+                let rating = ratings
+                    .remove(app.get_str("app_id").unwrap_or_default())
+                    .unwrap_or_else(|| Aggregate::default().to_document());
+                app.insert("rating", rating);
+            }
+            data.count_rating_totals();
+            Ok(())
+        })
+    }
+
+    async fn search_apps(&self, query: &search::Query) -> StorageResult<Vec<Document>> {
+        self.read(|data| {
+            data.search_index
+                .search(query)
+                .into_iter()
+                .filter(|(i, _)| published(&data.apps[*i]))
+                .map(|(i, relevance)| {
+                    let mut app = without_id(&data.apps[i]);
+                    app.insert("relevance", relevance);
+                    app
+                })
+                .collect()
+        })
+    }
+
+    async fn get_versions(&self, app_id: &str) -> StorageResult<Vec<Document>> {
+        self.read(|data| {
+            let mut versions: Vec<Document> = data
+                .apps_versions
+                .iter()
+                .filter(|v| str_eq(v, "app_id", app_id))
+                .map(without_id)
+                .collect();
+            versions.sort_by(|a, b| number(b, "timestamp").total_cmp(&number(a, "timestamp")));
+            versions
+        })
+    }
+
+    async fn get_versions_of_apps(&self, app_ids: &[String]) -> StorageResult<Vec<Document>> {
+        self.read(|data| {
+            let mut versions: Vec<Document> = data
+                .apps_versions
+                .iter()
+                .filter(|v| app_ids.iter().any(|id| str_eq(v, "app_id", id)))
+                .map(without_id)
+                .collect();
+            versions.sort_by(|a, b| number(b, "timestamp").total_cmp(&number(a, "timestamp")));
+            versions
+        })
+    }
+
+    async fn insert_version(&self, version: Document) -> StorageResult<()> {
+        self.write(|data| {
+            data.apps_versions.push(version);
+            Ok(())
+        })
+    }
+
+    async fn record_download(&self, download: Document) -> StorageResult<bool> {
+        self.write(|data| {
+            let exists = data.downloads.iter().any(|d| {
+                ["user_name", "app_id", "version"]
+                    .iter()
+                    .all(|key| d.get(key) == download.get(key))
+            });
+            if !exists {
+                data.downloads.push(download);
+            }
+            Ok(!exists)
+        })
+    }
+
+    async fn get_download_counts(
+        &self,
+        app_id: &str,
+        day_start: i64,
+        week_start: i64,
+    ) -> StorageResult<Vec<Document>> {
+        self.read(|data| {
+            let mut counts: BTreeMap<(&str, &str), [i64; 3]> = BTreeMap::new();
+            for download in data
+                .downloads
+                .iter()
+                .filter(|d| str_eq(d, "app_id", app_id))
+            {
+                let key = (
+                    download.get_str("version").unwrap_or_default(),
+                    download.get_str("platform").unwrap_or_default(),
+                );
+                let timestamp = number(download, "timestamp") as i64;
+                let count = counts.entry(key).or_default();
+                count[0] += i64::from(timestamp >= day_start);
+                count[1] += i64::from(timestamp >= week_start);
+                count[2] += 1;
+            }
+            counts
+                .into_iter()
+                .map(|((version, platform), [day, week, all])| {
+                    doc! {
+                        "version": version,
+                        "platform": platform,
+                        "day": day,
+                        "week": week,
+                        "all": all,
+                    }
+                })
+                .collect()
+        })
+    }
+
+    async fn get_reviews(&self, filter: &ReviewFilter, page: &PageRequest) -> StorageResult<Page> {
+        self.read(|data| {
+            page.paginate(
+                data.reviews
+                    .iter()
+                    .filter(|r| str_eq(r, "app_id", &filter.app_id) && !hidden(r))
+                    .filter(|r| {
+                        let score = number(r, "score");
+                        filter.min_score.is_none_or(|min| score >= min as f64)
+                            && filter.max_score.is_none_or(|max| score <= max as f64)
+                    })
+                    .filter(|r| {
+                        !filter.with_text
+                            || !r.get_str("text").unwrap_or_default().trim().is_empty()
+                    })
+                    .filter(|r| match &filter.version {
+                        Some(version) => str_eq(r, "version", version),
+                        None => true,
+                    })
+                    .map(without_id)
+                    .collect(),
+            )
+        })
+    }
+
+    async fn get_score_counts(&self) -> StorageResult<Vec<Document>> {
+        self.read(|data| {
+            let mut counts: BTreeMap<(&str, i64), i64> = BTreeMap::new();
+            for review in data.reviews.iter().filter(|r| !hidden(r)) {
+                let key = (
+                    review.get_str("app_id").unwrap_or_default(),
+                    number(review, "score") as i64,
+                );
+                *counts.entry(key).or_default() += 1;
+            }
+            counts
+                .into_iter()
+                .map(|((app_id, score), count)| {
+                    doc! {"app_id": app_id, "score": score, "count": count}
+                })
+                .collect()
+        })
+    }
+
+    async fn find_review(&self, user_name: &str, app_id: &str) -> StorageResult<Option<Document>> {
+        self.read(|data| {
+            data.reviews
+                .iter()
+                .find(|r| str_eq(r, "user_name", user_name) && str_eq(r, "app_id", app_id))
+                .cloned()
+        })
+    }
+
+    async fn get_reviews_by_user(&self, user_name: &str) -> StorageResult<Vec<Document>> {
+        self.read(|data| {
+            let mut reviews: Vec<Document> = data
+                .reviews
+                .iter()
+                .filter(|r| str_eq(r, "user_name", user_name))
+                .map(without_id)
+                .collect();
+            reviews.sort_by(|a, b| number(b, "timestamp").total_cmp(&number(a, "timestamp")));
+            reviews
+        })
+    }
+
+    async fn upsert_review(
+        &self,
+        user_name: &str,
+        app_id: &str,
+        review: Document,
+    ) -> StorageResult<Option<Document>> {
+        self.write(|data| {
+            let (previous, current) = match data
+                .reviews
+                .iter_mut()
+                .find(|r| str_eq(r, "user_name", user_name) && str_eq(r, "app_id", app_id))
+            {
+                Some(existing) => {
+                    let previous = existing.clone();
+                    existing.extend(review);
+                    (Some(previous), existing.clone())
+                }
+                None => {
+                    let mut review = review;
+                    review.insert("user_name", user_name);
+                    review.insert("app_id", app_id);
+                    review.insert("helpful", 0);
+                    review.insert("unhelpful", 0);
+                    data.reviews.push(review.clone());
+                    (None, review)
+                }
+            };
+            data.change_rating(app_id, previous.as_ref(), Some(&current));
+            Ok(previous)
+        })
+    }
+
+    async fn update_review(
+        &self,
+        user_name: &str,
+        app_id: &str,
+        fields: Document,
+    ) -> StorageResult<Option<Document>> {
+        self.write(|data| {
+            let (previous, current) = match data
+                .reviews
+                .iter_mut()
+                .find(|r| str_eq(r, "user_name", user_name) && str_eq(r, "app_id", app_id))
+            {
+                Some(existing) => {
+                    let previous = existing.clone();
+                    existing.extend(fields);
+                    (previous, existing.clone())
+                }
+                None => return Ok(None),
+            };
+            data.change_rating(app_id, Some(&previous), Some(&current));
+            Ok(Some(previous))
+        })
+    }
+
+    async fn delete_review(
+        &self,
+        user_name: &str,
+        app_id: &str,
+    ) -> StorageResult<Option<Document>> {
+        self.write(|data| {
+            let position = data
+                .reviews
+                .iter()
+                .position(|r| str_eq(r, "user_name", user_name) && str_eq(r, "app_id", app_id));
+            let previous = position.map(|i| data.reviews.remove(i));
+            if previous.is_some() {
+                data.change_rating(app_id, previous.as_ref(), None);
+                data.review_votes
+                    .retain(|v| !(str_eq(v, "app_id", app_id) && str_eq(v, "author", user_name)));
+            }
+            Ok(previous)
+        })
+    }
+
+    async fn vote_review(
+        &self,
+        app_id: &str,
+        author: &str,
+        voter: &str,
+        helpful: Option<bool>,
+    ) -> StorageResult<()> {
+        self.write(|data| {
+            let position = data.review_votes.iter().position(|v| {
+                str_eq(v, "app_id", app_id)
+                    && str_eq(v, "author", author)
+                    && str_eq(v, "user_name", voter)
+            });
+            let previous = position.map(|i| data.review_votes.remove(i));
+            let previous = previous.and_then(|vote| vote.get_bool("helpful").ok());
+            if let Some(helpful) = helpful {
+                data.review_votes.push(doc! {
+                    "app_id": app_id,
+                    "author": author,
+                    "user_name": voter,
+                    "helpful": helpful,
+                });
+            }
+
+            if let Some(review) = data
+                .reviews
+                .iter_mut()
+                .find(|r| str_eq(r, "app_id", app_id) && str_eq(r, "user_name", author))
+            {
+                for (key, change) in vote_tallies(previous, helpful) {
+                    let tally = number(review, &key) as i32 + change.as_i32().unwrap_or_default();
+                    review.insert(key, tally);
+                }
+            }
+            Ok(())
+        })
+    }
+
+    async fn insert_review_revision(&self, revision: Document) -> StorageResult<()> {
+        self.write(|data| {
+            data.review_revisions.push(revision);
+            Ok(())
+        })
+    }
+
+    async fn insert_review_report(&self, report: Document) -> StorageResult<()> {
+        self.write(|data| {
+            let open = data.review_reports.iter().any(|r| {
+                str_eq(r, "status", "open")
+                    && ["app_id", "author", "user_name"]
+                        .iter()
+                        .all(|key| r.get(key) == report.get(key))
+            });
+            if open {
+                return Err(StorageError::Duplicate);
+            }
+            data.review_reports.push(report);
+            Ok(())
+        })
+    }
+
+    async fn get_moderation_queue(&self, page: &PageRequest) -> StorageResult<Page> {
+        self.read(|data| {
+            let mut reports: Vec<&Document> = data
+                .review_reports
+                .iter()
+                .filter(|r| str_eq(r, "status", "open"))
+                .collect();
+            reports.sort_by(|a, b| number(a, "timestamp").total_cmp(&number(b, "timestamp")));
+
+            let mut groups: HashMap<(&str, &str), Vec<Document>> = HashMap::new();
+            for report in reports {
+                let app_id = report.get_str("app_id").unwrap_or_default();
+                let author = report.get_str("author").unwrap_or_default();
+                groups
+                    .entry((app_id, author))
+                    .or_default()
+                    .push(without_id(report));
+            }
+            let queue = groups
+                .into_iter()
+                .map(|((app_id, author), reports)| {
+                    let review = data
+                        .reviews
+                        .iter()
+                        .find(|r| str_eq(r, "app_id", app_id) && str_eq(r, "user_name", author))
+                        .map(without_id);
+                    doc! {
+                        "app_id": app_id,
+                        "user_name": author,
+                        "review": review,
+                        "count": reports.len() as i64,
+                        "since": reports[0].get("timestamp").cloned(),
+                        "reports": reports,
+                    }
+                })
+                .collect();
+            page.paginate(queue)
+        })
+    }
+
+    async fn resolve_review_reports(
+        &self,
+        app_id: &str,
+        author: &str,
+        fields: Document,
+    ) -> StorageResult<()> {
+        self.write(|data| {
+            for report in data.review_reports.iter_mut().filter(|r| {
+                str_eq(r, "app_id", app_id)
+                    && str_eq(r, "author", author)
+                    && str_eq(r, "status", "open")
+            }) {
+                report.extend(fields.clone());
+            }
+            Ok(())
+        })
+    }
+
+    async fn get_revisions_by_user(
+        &self,
+        user_name: &str,
+        since: i64,
+    ) -> StorageResult<Vec<Document>> {
+        self.read(|data| {
+            let mut revisions: Vec<Document> = data
+                .review_revisions
+                .iter()
+                .filter(|r| {
+                    str_eq(r, "user_name", user_name) && number(r, "timestamp") >= since as f64
+                })
+                .map(without_id)
+                .collect();
+            revisions.sort_by(|a, b| number(b, "timestamp").total_cmp(&number(a, "timestamp")));
+            revisions
+        })
+    }
+
+    async fn get_review_revisions(
+        &self,
+        user_name: &str,
+        app_id: &str,
+    ) -> StorageResult<Vec<Document>> {
+        self.read(|data| {
+            let mut revisions: Vec<Document> = data
+                .review_revisions
+                .iter()
+                .filter(|r| str_eq(r, "user_name", user_name) && str_eq(r, "app_id", app_id))
+                .map(without_id)
+                .collect();
+            revisions.sort_by(|a, b| number(b, "replaced").total_cmp(&number(a, "replaced")));
+            revisions
+        })
+    }
+
+    async fn get_tags(&self) -> StorageResult<Vec<Document>> {
+        self.read(|data| {
+            let mut tags: Vec<Document> = data.tags.iter().map(without_id).collect();
+            tags.sort_by(|a, b| {
+                a.get_str("slug")
+                    .unwrap_or_default()
+                    .cmp(b.get_str("slug").unwrap_or_default())
+            });
+            tags
+        })
+    }
+
+    async fn insert_tag(&self, tag: Document) -> StorageResult<()> {
+        self.write(|data| {
+            let slug = tag.get_str("slug").unwrap_or_default();
+            if find(&data.tags, "slug", slug).is_some() {
+                return Err(StorageError::Duplicate);
+            }
+            data.tags.push(tag);
+            Ok(())
+        })
+    }
+
+    async fn update_tag(&self, slug: &str, fields: Document) -> StorageResult<()> {
+        self.write(|data| {
+            if let Some(tag) = find_mut(&mut data.tags, "slug", slug) {
+                tag.extend(fields);
+            }
+            Ok(())
+        })
+    }
+
+    async fn delete_tag(&self, slug: &str) -> StorageResult<()> {
+        self.write(|data| {
+            data.tags.retain(|tag| !str_eq(tag, "slug", slug));
+            Ok(())
+        })
+    }
+
+    async fn find_personal_library(&self, name: &str) -> StorageResult<Option<Document>> {
+        self.read(|data| find(&data.personal_libraries, "name", name).cloned())
+    }
+
+    async fn create_personal_library(&self, name: &str) -> StorageResult<()> {
+        self.write(|data| {
+            data.personal_libraries.push(doc! {
+                "name":name,
+                "apps":[]
+            });
+            Ok(())
+        })
+    }
+
+    async fn add_to_personal_library(&self, name: &str, app_id: &str) -> StorageResult<()> {
+        self.write(|data| {
+            match find_mut(&mut data.personal_libraries, "name", name) {
+                Some(library) => {
+                    let apps = library_apps(library);
+                    if apps.iter().any(|app| app.as_str() == Some(app_id)) {
+                        return Err(StorageError::Duplicate);
+                    }
+                    apps.push(Bson::String(app_id.to_string()));
+                }
+                None => data
+                    .personal_libraries
+                    .push(doc! {"name": name, "apps": [app_id]}),
+            }
+            Ok(())
+        })
+    }
+
+    async fn delete_from_personal_library(&self, name: &str, app_id: &str) -> StorageResult<()> {
+        self.write(|data| {
+            if let Some(library) = find_mut(&mut data.personal_libraries, "name", name) {
+                library_apps(library).retain(|app| app.as_str() != Some(app_id));
+            }
+            Ok(())
+        })
+    }
+
+    async fn insert_session(&self, session: Document) -> StorageResult<()> {
+        self.write(|data| {
+            data.sessions.push(session);
+            Ok(())
+        })
+    }
+
+    async fn find_session(&self, session_id: &str) -> StorageResult<Option<Document>> {
+        self.read(|data| find(&data.sessions, "session_id", session_id).cloned())
+    }
+
+    async fn rotate_session(
+        &self,
+        session_id: &str,
+        refresh_hash: &str,
+        fields: Document,
+    ) -> StorageResult<bool> {
+        self.write(
+            |data| match find_mut(&mut data.sessions, "session_id", session_id) {
+                Some(session) if str_eq(session, "refresh_hash", refresh_hash) => {
+                    session.extend(fields);
+                    Ok(true)
+                }
+                _ => Ok(false),
+            },
+        )
+    }
+
+    async fn delete_session(&self, session_id: &str) -> StorageResult<()> {
+        self.write(|data| {
+            data.sessions
+                .retain(|s| !str_eq(s, "session_id", session_id));
+            Ok(())
+        })
+    }
+
+    async fn delete_user_sessions(&self, name: &str) -> StorageResult<()> {
+        self.write(|data| {
+            data.sessions.retain(|s| !str_eq(s, "name", name));
+            Ok(())
+        })
+    }
+}
@@ -0,0 +1,241 @@
+// Copyright (c) 2023 artegoser (Artemy Egorov)
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+use async_trait::async_trait;
+use bson::{doc, Document};
+use std::collections::BTreeMap;
+use std::fmt;
+
+use crate::pagination::{Page, PageRequest};
+use crate::search;
+
+pub mod memory;
+pub mod mongo;
+
+pub use memory::MemoryStorage;
+pub use mongo::MongoStorage;
+
+pub type StorageResult<T> = Result<T, StorageError>;
+
+#[derive(Debug)]
+pub enum StorageError {
+    /// A unique constraint (user name, library entry, ...) would be violated
+    Duplicate,
+    /// The document was rejected by the collection validator
+    Invalid,
+    /// The backend failed or rejected the operation
+    Backend(Box<dyn std::error::Error + Send + Sync>),
+}
+
+impl fmt::Display for StorageError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StorageError::Duplicate => write!(f, "duplicate entry"),
+            StorageError::Invalid => write!(f, "document failed validation"),
+            StorageError::Backend(e) => write!(f, "storage backend error: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for StorageError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            StorageError::Duplicate | StorageError::Invalid => None,
+            StorageError::Backend(e) => Some(e.as_ref()),
+        }
+    }
+}
+
+/// Tags apps must have, every part is ignored when empty
+#[derive(Debug, Default)]
+pub struct TagFilter {
+    /// At least one of these tags
+    pub any: Vec<String>,
+    /// At least one tag of every group
+    pub all: Vec<Vec<String>>,
+    /// None of these tags
+    pub exclude: Vec<String>,
+}
+
+/// Reviews of an app to list, `None` parts are ignored
+#[derive(Debug, Default)]
+pub struct ReviewFilter {
+    pub app_id: String,
+    pub min_score: Option<i32>,
+    pub max_score: Option<i32>,
+    /// Only reviews with more than whitespace in `text`
+    pub with_text: bool,
+    pub version: Option<String>,
+}
+
+/// Changes of the `helpful` and `unhelpful` tallies of a review when a vote changes
+fn vote_tallies(previous: Option<bool>, current: Option<bool>) -> Document {
+    let mut tallies = doc! {"helpful": 0, "unhelpful": 0};
+    for (vote, change) in [(previous, -1), (current, 1)] {
+        if let Some(helpful) = vote {
+            let key = if helpful { "helpful" } else { "unhelpful" };
+            let tally = tallies.get_i32(key).unwrap_or_default();
+            tallies.insert(key, tally + change);
+        }
+    }
+    tallies
+}
+
+/// Persistence operations used by `Core`.
+///
+/// Listings never contain the `_id` field, single document lookups may.
+#[async_trait]
+pub trait Storage: Send + Sync {
+    //schema
+    /// Brings validators and indexes of the backend up to date, only reports the drift when
+    /// `apply` is false. Returns a line for every difference found
+    async fn migrate(&self, apply: bool) -> StorageResult<Vec<String>>;
+
+    //users
+    async fn find_user(&self, name: &str) -> StorageResult<Option<Document>>;
+    /// First user whose string `field` equals `value`
+    async fn find_user_by(&self, field: &str, value: &str) -> StorageResult<Option<Document>>;
+    async fn insert_user(&self, user: Document) -> StorageResult<()>;
+    /// Sets the given fields on the user, missing users are ignored
+    async fn update_user(&self, name: &str, fields: Document) -> StorageResult<()>;
+
+    //apps
+    /// Published apps, the same goes for other app listings
+    async fn get_apps(&self, page: &PageRequest) -> StorageResult<Page>;
+    /// Apps matching every part of the tag filter, empty parts are ignored
+    async fn get_apps_by_tags(&self, tags: &TagFilter, page: &PageRequest) -> StorageResult<Page>;
+    /// `{"tag", "count"}` of every tag of the apps matching the filter, most used first
+    async fn get_tag_counts(&self, tags: &TagFilter) -> StorageResult<Vec<Document>>;
+    /// Replaces the tag in every app, apps that already have `to` only lose `from`
+    async fn replace_app_tag(&self, from: &str, to: &str) -> StorageResult<()>;
+    /// All apps of the author, including unpublished ones
+    async fn get_apps_by_author(&self, author: &str, page: &PageRequest) -> StorageResult<Page>;
+    async fn find_app(&self, app_id: &str) -> StorageResult<Option<Document>>;
+    async fn insert_app(&self, app: Document) -> StorageResult<()>;
+    async fn update_app(&self, app_id: &str, fields: Document) -> StorageResult<()>;
+    /// `count` and `sum` of the `rating` of all apps added up
+    async fn get_rating_totals(&self) -> StorageResult<(i64, i64)>;
+    /// Sets the `rating` of every app, apps that are left out get an empty one
+    async fn set_ratings(&self, ratings: BTreeMap<String, Document>) -> StorageResult<()>;
+    /// Published apps matching the query, most relevant first with a `relevance` field
+    async fn search_apps(&self, query: &search::Query) -> StorageResult<Vec<Document>>;
+
+    //apps_versions
+    /// Versions of the app, newest release first
+    async fn get_versions(&self, app_id: &str) -> StorageResult<Vec<Document>>;
+    /// Versions of all the given apps in one query, newest release first
+    async fn get_versions_of_apps(&self, app_ids: &[String]) -> StorageResult<Vec<Document>>;
+    async fn insert_version(&self, version: Document) -> StorageResult<()>;
+
+    //downloads
+    /// Records the download unless the same user or visitor already downloaded this version, returns whether it did
+    async fn record_download(&self, download: Document) -> StorageResult<bool>;
+    /// `{"version", "platform", "day", "week", "all"}` download counts of the app,
+    /// `day` and `week` count downloads from the given timestamps on
+    async fn get_download_counts(
+        &self,
+        app_id: &str,
+        day_start: i64,
+        week_start: i64,
+    ) -> StorageResult<Vec<Document>>;
+
+    //reviews
+    /// Hidden reviews are never listed
+    async fn get_reviews(&self, filter: &ReviewFilter, page: &PageRequest) -> StorageResult<Page>;
+    /// `{"app_id", "score", "count"}` for every score given to any app, hidden reviews are left out
+    async fn get_score_counts(&self) -> StorageResult<Vec<Document>>;
+    async fn find_review(&self, user_name: &str, app_id: &str) -> StorageResult<Option<Document>>;
+    /// Every review written by the user, hidden ones too, newest first
+    async fn get_reviews_by_user(&self, user_name: &str) -> StorageResult<Vec<Document>>;
+    /// Creates or replaces the review of the app written by `user_name` and
+    /// updates the `rating` of the app with it, returns the replaced review
+    async fn upsert_review(
+        &self,
+        user_name: &str,
+        app_id: &str,
+        review: Document,
+    ) -> StorageResult<Option<Document>>;
+    /// Sets fields of an existing review and updates the `rating` of the app, returns the review before
+    async fn update_review(
+        &self,
+        user_name: &str,
+        app_id: &str,
+        fields: Document,
+    ) -> StorageResult<Option<Document>>;
+    /// Deletes the review with its votes and updates the `rating` of the app, returns the deleted review
+    async fn delete_review(&self, user_name: &str, app_id: &str)
+        -> StorageResult<Option<Document>>;
+    /// Sets, or removes with `None`, the vote of `voter` on the review of `author`
+    /// and updates the `helpful` and `unhelpful` tallies of the review
+    async fn vote_review(
+        &self,
+        app_id: &str,
+        author: &str,
+        voter: &str,
+        helpful: Option<bool>,
+    ) -> StorageResult<()>;
+    async fn insert_review_revision(&self, revision: Document) -> StorageResult<()>;
+    /// Fails with `Duplicate` while the reporter has an open report on the review
+    async fn insert_review_report(&self, report: Document) -> StorageResult<()>;
+    /// Open reports grouped by review as `{"app_id", "user_name", "review", "count", "since", "reports"}`,
+    /// `since` is the time of the oldest report and the reports are oldest first
+    async fn get_moderation_queue(&self, page: &PageRequest) -> StorageResult<Page>;
+    /// Sets the fields on the open reports of the review of `author`
+    async fn resolve_review_reports(
+        &self,
+        app_id: &str,
+        author: &str,
+        fields: Document,
+    ) -> StorageResult<()>;
+    /// Revisions of every review of the user written from `since` on
+    async fn get_revisions_by_user(
+        &self,
+        user_name: &str,
+        since: i64,
+    ) -> StorageResult<Vec<Document>>;
+    /// Earlier states of the review, newest first
+    async fn get_review_revisions(
+        &self,
+        user_name: &str,
+        app_id: &str,
+    ) -> StorageResult<Vec<Document>>;
+
+    //tags
+    /// The whole tag catalog
+    async fn get_tags(&self) -> StorageResult<Vec<Document>>;
+    async fn insert_tag(&self, tag: Document) -> StorageResult<()>;
+    async fn update_tag(&self, slug: &str, fields: Document) -> StorageResult<()>;
+    async fn delete_tag(&self, slug: &str) -> StorageResult<()>;
+
+    //personal_libraries
+    async fn find_personal_library(&self, name: &str) -> StorageResult<Option<Document>>;
+    async fn create_personal_library(&self, name: &str) -> StorageResult<()>;
+    /// Creates the library if the user has none, `Duplicate` only if the app is already in it
+    async fn add_to_personal_library(&self, name: &str, app_id: &str) -> StorageResult<()>;
+    async fn delete_from_personal_library(&self, name: &str, app_id: &str) -> StorageResult<()>;
+
+    //sessions
+    async fn insert_session(&self, session: Document) -> StorageResult<()>;
+    async fn find_session(&self, session_id: &str) -> StorageResult<Option<Document>>;
+    /// Sets the fields if the session still has `refresh_hash`, returns whether it did
+    async fn rotate_session(
+        &self,
+        session_id: &str,
+        refresh_hash: &str,
+        fields: Document,
+    ) -> StorageResult<bool>;
+    async fn delete_session(&self, session_id: &str) -> StorageResult<()>;
+    async fn delete_user_sessions(&self, name: &str) -> StorageResult<()>;
+}
@@ -0,0 +1,1186 @@
+// Copyright (c) 2023 artegoser (Artemy Egorov)
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+use async_trait::async_trait;
+use bson::{doc, Bson, Document};
+use futures::TryStreamExt;
+use mongodb::{
+    error::{ErrorKind, WriteFailure, TRANSIENT_TRANSACTION_ERROR},
+    options::{
+        CreateCollectionOptions, FindOneAndUpdateOptions, FindOptions, IndexOptions,
+        ReplaceOptions, ReturnDocument, UpdateOptions,
+    },
+    ClientSession, Collection, Database, IndexModel,
+};
+use std::collections::BTreeMap;
+
+use super::{vote_tallies, ReviewFilter, Storage, StorageError, StorageResult, TagFilter};
+use crate::pagination::{Cursor, Page, PageRequest, Sort};
+use crate::rating::{self, Aggregate};
+use crate::search;
+
+/// `$jsonSchema` validators of the collections, the files are the documentation of the schema
+const VALIDATORS: [(&str, &str); 11] = [
+    ("users", include_str!("../../docs/db-validation/users.json")),
+    ("apps", include_str!("../../docs/db-validation/apps.json")),
+    (
+        "apps_versions",
+        include_str!("../../docs/db-validation/apps_versions.json"),
+    ),
+    (
+        "reviews",
+        include_str!("../../docs/db-validation/reviews.json"),
+    ),
+    (
+        "review_revisions",
+        include_str!("../../docs/db-validation/review_revisions.json"),
+    ),
+    (
+        "review_votes",
+        include_str!("../../docs/db-validation/review_votes.json"),
+    ),
+    (
+        "review_reports",
+        include_str!("../../docs/db-validation/review_reports.json"),
+    ),
+    (
+        "personal_libraries",
+        include_str!("../../docs/db-validation/personal_libraries.json"),
+    ),
+    ("tags", include_str!("../../docs/db-validation/tags.json")),
+    (
+        "downloads",
+        include_str!("../../docs/db-validation/downloads.json"),
+    ),
+    (
+        "rating_totals",
+        include_str!("../../docs/db-validation/rating_totals.json"),
+    ),
+];
+
+/// `_id` of the only document in `rating_totals`
+const RATING_TOTALS: &str = "all";
+
+/// Collection, keys and uniqueness of the indexes the storage relies on
+fn required_indexes() -> Vec<(&'static str, Document, bool)> {
+    vec![
+        ("users", doc! {"name": 1}, true),
+        ("apps", doc! {"app_id": 1}, true),
+        ("apps_versions", doc! {"app_id": 1, "timestamp": -1}, false),
+        ("reviews", doc! {"app_id": 1, "user_name": 1}, true),
+        ("reviews", doc! {"user_name": 1, "timestamp": -1}, false),
+        (
+            "review_votes",
+            doc! {"app_id": 1, "author": 1, "user_name": 1},
+            true,
+        ),
+        ("review_reports", doc! {"status": 1, "timestamp": 1}, false),
+        (
+            "review_revisions",
+            doc! {"app_id": 1, "user_name": 1, "replaced": -1},
+            false,
+        ),
+        (
+            "review_revisions",
+            doc! {"user_name": 1, "timestamp": -1},
+            false,
+        ),
+        ("personal_libraries", doc! {"name": 1}, true),
+        ("sessions", doc! {"session_id": 1}, true),
+        ("tags", doc! {"slug": 1}, true),
+        (
+            "downloads",
+            doc! {"app_id": 1, "user_name": 1, "version": 1},
+            true,
+        ),
+    ]
+}
+
+fn validator(schema: &str) -> StorageResult<Document> {
+    let schema: serde_json::Value =
+        serde_json::from_str(schema).map_err(|e| StorageError::Backend(Box::new(e)))?;
+    bson::to_document(&schema).map_err(|e| StorageError::Backend(Box::new(e)))
+}
+
+/// Compared as json so integer widths do not count as drift
+fn same_document(a: &Document, b: &Document) -> bool {
+    Bson::Document(a.clone()).into_relaxed_extjson()
+        == Bson::Document(b.clone()).into_relaxed_extjson()
+}
+
+/// Same fields in the same order and directions, `1` and `1.0` are the same
+fn same_keys(a: &Document, b: &Document) -> bool {
+    let direction = |value: &Bson| match value {
+        Bson::Int32(n) => Some(*n as f64),
+        Bson::Int64(n) => Some(*n as f64),
+        Bson::Double(n) => Some(*n),
+        _ => None,
+    };
+    a.len() == b.len()
+        && a.iter()
+            .zip(b.iter())
+            .all(|((ka, va), (kb, vb))| ka == kb && direction(va) == direction(vb))
+}
+
+/// Change of a review, the rating of the app changes with it
+enum ReviewChange {
+    /// Sets the fields, creating the review if there is none
+    Upsert(Document),
+    /// Sets the fields of an existing review
+    Update(Document),
+    Delete,
+}
+
+pub struct MongoStorage {
+    db: Database,
+    users: Collection<Document>,
+    apps: Collection<Document>,
+    apps_versions: Collection<Document>,
+    reviews: Collection<Document>,
+    review_revisions: Collection<Document>,
+    review_votes: Collection<Document>,
+    review_reports: Collection<Document>,
+    personal_libraries: Collection<Document>,
+    sessions: Collection<Document>,
+    tags: Collection<Document>,
+    downloads: Collection<Document>,
+    /// `count` and `sum` of the `rating` of all apps added up
+    rating_totals: Collection<Document>,
+}
+
+impl MongoStorage {
+    pub fn new(db: &Database) -> MongoStorage {
+        MongoStorage {
+            db: db.clone(),
+            users: db.collection("users"),
+            apps: db.collection("apps"),
+            apps_versions: db.collection("apps_versions"),
+            reviews: db.collection("reviews"),
+            review_revisions: db.collection("review_revisions"),
+            review_votes: db.collection("review_votes"),
+            review_reports: db.collection("review_reports"),
+            personal_libraries: db.collection("personal_libraries"),
+            sessions: db.collection("sessions"),
+            tags: db.collection("tags"),
+            downloads: db.collection("downloads"),
+            rating_totals: db.collection("rating_totals"),
+        }
+    }
+
+    /// Sums up the ratings of all apps into `rating_totals`, reviews written meanwhile may be missed
+    async fn count_rating_totals(&self) -> StorageResult<()> {
+        let mut cursor = self
+            .apps
+            .aggregate(
+                [doc! {
+                    "$group": {
+                        "_id": null,
+                        "count": {"$sum": "$rating.count"},
+                        "sum": {"$sum": "$rating.sum"},
+                    },
+                }],
+                None,
+            )
+            .await?;
+        let totals = match cursor.try_next().await? {
+            Some(totals) => Aggregate::of(&doc! {"rating": totals}),
+            None => Aggregate::default(),
+        };
+        self.rating_totals
+            .replace_one(
+                doc! {"_id": RATING_TOTALS},
+                doc! {"count": totals.count, "sum": totals.sum},
+                ReplaceOptions::builder().upsert(Some(true)).build(),
+            )
+            .await?;
+        Ok(())
+    }
+
+    /// Changes the review and updates the rating of the app in one transaction
+    async fn change_review(
+        &self,
+        user_name: &str,
+        app_id: &str,
+        change: ReviewChange,
+    ) -> StorageResult<Option<Document>> {
+        let mut session = self.reviews.client().start_session(None).await?;
+        let mut attempt = 1;
+        loop {
+            session.start_transaction(None).await?;
+            let result = match self
+                .try_change_review(&mut session, user_name, app_id, &change)
+                .await
+            {
+                Ok(previous) => session.commit_transaction().await.map(|_| previous),
+                Err(e) => {
+                    session.abort_transaction().await.ok();
+                    Err(e)
+                }
+            };
+            match result {
+                Err(e) if e.contains_label(TRANSIENT_TRANSACTION_ERROR) && attempt < 3 => {
+                    attempt += 1
+                }
+                result => return Ok(result?),
+            }
+        }
+    }
+
+    async fn try_change_review(
+        &self,
+        session: &mut ClientSession,
+        user_name: &str,
+        app_id: &str,
+        change: &ReviewChange,
+    ) -> mongodb::error::Result<Option<Document>> {
+        let filter = doc! {"user_name": user_name, "app_id": app_id};
+        let (previous, current) = match change {
+            ReviewChange::Upsert(fields) | ReviewChange::Update(fields) => {
+                let upsert = matches!(change, ReviewChange::Upsert(_));
+                let options = FindOneAndUpdateOptions::builder()
+                    .upsert(upsert)
+                    .return_document(ReturnDocument::Before)
+                    .build();
+                let previous = self
+                    .reviews
+                    .find_one_and_update_with_session(
+                        filter,
+                        doc! {"$set": fields, "$setOnInsert": {"helpful": 0, "unhelpful": 0}},
+                        options,
+                        session,
+                    )
+                    .await?;
+                let current = match &previous {
+                    Some(previous) => {
+                        let mut current = previous.clone();
+                        current.extend(fields.clone());
+                        Some(current)
+                    }
+                    None if upsert => Some(fields.clone()),
+                    None => None,
+                };
+                (previous, current)
+            }
+            ReviewChange::Delete => {
+                self.review_votes
+                    .delete_many_with_session(
+                        doc! {"app_id": app_id, "author": user_name},
+                        None,
+                        session,
+                    )
+                    .await?;
+                let previous = self
+                    .reviews
+                    .find_one_and_delete_with_session(filter, None, session)
+                    .await?;
+                (previous, None)
+            }
+        };
+        if previous.is_none() && current.is_none() {
+            return Ok(None);
+        }
+
+        let mut rating = match self
+            .apps
+            .find_one_with_session(doc! {"app_id": app_id}, None, session)
+            .await?
+        {
+            Some(app) => Aggregate::of(&app),
+            None => Aggregate::default(),
+        };
+        let (count, sum) = (rating.count, rating.sum);
+        if let Some(previous) = &previous {
+            rating.add(previous, -1);
+        }
+        if let Some(current) = &current {
+            rating.add(current, 1);
+        }
+        self.apps
+            .update_one_with_session(
+                doc! {"app_id": app_id},
+                doc! {"$set": {"rating": rating.to_document()}},
+                None,
+                session,
+            )
+            .await?;
+        self.rating_totals
+            .update_one_with_session(
+                doc! {"_id": RATING_TOTALS},
+                doc! {"$inc": {"count": rating.count - count, "sum": rating.sum - sum}},
+                UpdateOptions::builder().upsert(Some(true)).build(),
+                session,
+            )
+            .await?;
+        Ok(previous)
+    }
+
+    async fn get_collection_with_params_and_sort(
+        &self,
+        collection: &Collection<Document>,
+        params: Document,
+        sort_params: Document,
+    ) -> StorageResult<Vec<Document>> {
+        let options = FindOptions::builder()
+            .projection(doc! {"_id" : 0})
+            .sort(sort_params)
+            .build();
+        let cursor = collection.find(params, options).await?;
+        Ok(cursor.try_collect().await?)
+    }
+
+    async fn get_page(
+        &self,
+        collection: &Collection<Document>,
+        params: Document,
+        page: &PageRequest,
+    ) -> StorageResult<Page> {
+        let total = match page.count {
+            true => Some(collection.count_documents(params.clone(), None).await?),
+            false => None,
+        };
+
+        let fetch_sort = page.fetch_sort();
+        let params = match &page.cursor {
+            Some(cursor) => doc! {"$and": [params, keyset_filter(&fetch_sort, cursor)]},
+            None => params,
+        };
+        let mut sort_params = Document::new();
+        for (field, direction) in &fetch_sort {
+            sort_params.insert(field, direction);
+        }
+        let options = FindOptions::builder()
+            .projection(doc! {"_id" : 0})
+            .sort(sort_params)
+            .limit(page.limit as i64 + 1)
+            .build();
+
+        let cursor = collection.find(params, options).await?;
+        Ok(page.page(cursor.try_collect().await?, total))
+    }
+}
+
+/// Mongodb filter for the items after the cursor in `fetch_sort` order
+fn keyset_filter(fetch_sort: &Sort, cursor: &Cursor) -> Document {
+    let mut alternatives = vec![];
+    for i in 0..fetch_sort.len() {
+        let mut alternative = Document::new();
+        for ((field, _), value) in fetch_sort.iter().zip(&cursor.key).take(i) {
+            alternative.insert(field, value.clone());
+        }
+        let (field, direction) = &fetch_sort[i];
+        let operator = if *direction < 0 { "$lt" } else { "$gt" };
+        alternative.insert(field, doc! {operator: cursor.key[i].clone()});
+        alternatives.push(Bson::Document(alternative));
+    }
+    doc! {"$or": alternatives}
+}
+/// Published apps matching the tag filter
+fn tag_filter(tags: &TagFilter) -> Document {
+    let mut conditions = vec![doc! {"published":{"$ne":false}}];
+    if !tags.any.is_empty() {
+        conditions.push(doc! {"tags": {"$in": &tags.any}});
+    }
+    for group in &tags.all {
+        conditions.push(doc! {"tags": {"$in": group}});
+    }
+    if !tags.exclude.is_empty() {
+        conditions.push(doc! {"tags": {"$nin": &tags.exclude}});
+    }
+    doc! {"$and": conditions}
+}
+
+impl From<mongodb::error::Error> for StorageError {
+    fn from(e: mongodb::error::Error) -> Self {
+        match e.kind.as_ref() {
+            ErrorKind::Write(WriteFailure::WriteError(err)) if err.code == 11000 => {
+                StorageError::Duplicate
+            }
+            ErrorKind::Write(WriteFailure::WriteError(err)) if err.code == 121 => {
+                StorageError::Invalid
+            }
+            _ => StorageError::Backend(Box::new(e)),
+        }
+    }
+}
+
+#[async_trait]
+impl Storage for MongoStorage {
+    async fn migrate(&self, apply: bool) -> StorageResult<Vec<String>> {
+        let mut report = vec![];
+        //data is fixed before the validators are applied, updates of invalid documents would fail
+        let untallied = doc! {"$or": [
+            {"helpful": {"$exists": false}},
+            {"unhelpful": {"$exists": false}},
+        ]};
+        if apply {
+            let tallies = doc! {"$set": {
+                "helpful": {"$ifNull": ["$helpful", 0]},
+                "unhelpful": {"$ifNull": ["$unhelpful", 0]},
+            }};
+            let result = self
+                .reviews
+                .update_many(untallied, vec![tallies], None)
+                .await?;
+            if result.modified_count > 0 {
+                report.push(format!(
+                    "reviews: added vote tallies to {} reviews",
+                    result.modified_count
+                ));
+            }
+        } else {
+            let count = self.reviews.count_documents(untallied, None).await?;
+            if count > 0 {
+                report.push(format!("reviews: {} reviews have no vote tallies", count));
+            }
+        }
+
+        let unrated: Vec<Document> = self
+            .apps
+            .find(
+                doc! {"rating": {"$exists": false}},
+                FindOptions::builder()
+                    .projection(doc! {"_id": 0, "app_id": 1})
+                    .build(),
+            )
+            .await?
+            .try_collect()
+            .await?;
+        if !unrated.is_empty() && apply {
+            let aggregates = rating::aggregates(&self.get_score_counts().await?);
+            for app in unrated.iter() {
+                let app_id = app.get_str("app_id").unwrap_or_default();
+                let rating = aggregates.get(app_id).cloned().unwrap_or_default();
+                self.apps
+                    .update_one(
+                        doc! {"app_id": app_id, "rating": {"$exists": false}},
+                        doc! {"$set": {"rating": rating.to_document()}},
+                        None,
+                    )
+                    .await?;
+            }
+            report.push(format!("apps: counted rating of {} apps", unrated.len()));
+        } else if !unrated.is_empty() {
+            report.push(format!("apps: {} apps have no rating", unrated.len()));
+        }
+
+        let uncounted = self
+            .rating_totals
+            .count_documents(doc! {"_id": RATING_TOTALS}, None)
+            .await?
+            == 0;
+        if apply && (uncounted || !unrated.is_empty()) {
+            self.count_rating_totals().await?;
+            report.push("rating_totals: counted the ratings of all apps".to_string());
+        } else if uncounted {
+            report.push("rating_totals: ratings of all apps are not counted".to_string());
+        }
+
+        let collections: Vec<_> = self
+            .db
+            .list_collections(None, None)
+            .await?
+            .try_collect()
+            .await?;
+
+        for (name, schema) in VALIDATORS {
+            let validator = validator(schema)?;
+            match collections.iter().find(|c| c.name == name) {
+                None if apply => {
+                    let options = CreateCollectionOptions::builder()
+                        .validator(validator)
+                        .build();
+                    self.db.create_collection(name, options).await?;
+                    report.push(format!("{}: created with validator", name));
+                }
+                None => report.push(format!("{}: collection is missing", name)),
+                Some(collection) => {
+                    let current = collection.options.validator.as_ref();
+                    if current.is_some_and(|current| same_document(current, &validator)) {
+                        continue;
+                    }
+                    if apply {
+                        self.db
+                            .run_command(doc! {"collMod": name, "validator": validator}, None)
+                            .await?;
+                        report.push(format!("{}: validator updated", name));
+                    } else if current.is_none() {
+                        report.push(format!("{}: validator is missing", name));
+                    } else {
+                        report.push(format!("{}: validator differs", name));
+                    }
+                }
+            }
+        }
+
+        for (name, keys, unique) in required_indexes() {
+            let collection = self.db.collection::<Document>(name);
+            let indexes: Vec<IndexModel> = match collection.list_indexes(None).await {
+                Ok(cursor) => cursor.try_collect().await?,
+                //NamespaceNotFound, the collection does not exist yet
+                Err(e) if matches!(e.kind.as_ref(), ErrorKind::Command(err) if err.code == 26) => {
+                    vec![]
+                }
+                Err(e) => return Err(e.into()),
+            };
+            let kind = if unique { "unique index" } else { "index" };
+            match indexes.iter().find(|index| same_keys(&index.keys, &keys)) {
+                Some(index) => {
+                    let is_unique = index
+                        .options
+                        .as_ref()
+                        .and_then(|options| options.unique)
+                        .unwrap_or(false);
+                    if is_unique != unique {
+                        //changing uniqueness needs the index dropped, which is left to the admin
+                        report.push(format!(
+                            "{}: index {} should be {}, drop it and migrate again",
+                            name,
+                            keys,
+                            if unique { "unique" } else { "not unique" }
+                        ));
+                    }
+                }
+                None if apply => {
+                    let options = IndexOptions::builder().unique(unique).build();
+                    collection
+                        .create_index(
+                            IndexModel::builder()
+                                .keys(keys.clone())
+                                .options(options)
+                                .build(),
+                            None,
+                        )
+                        .await?;
+                    report.push(format!("{}: created {} {}", name, kind, keys));
+                }
+                None => report.push(format!("{}: missing {} {}", name, kind, keys)),
+            }
+        }
+        Ok(report)
+    }
+
+    async fn find_user(&self, name: &str) -> StorageResult<Option<Document>> {
+        Ok(self.users.find_one(doc! {"name":name}, None).await?)
+    }
+
+    async fn find_user_by(&self, field: &str, value: &str) -> StorageResult<Option<Document>> {
+        Ok(self.users.find_one(doc! {field: value}, None).await?)
+    }
+
+    async fn insert_user(&self, user: Document) -> StorageResult<()> {
+        self.users.insert_one(user, None).await?;
+        Ok(())
+    }
+
+    async fn update_user(&self, name: &str, fields: Document) -> StorageResult<()> {
+        self.users
+            .update_one(doc! {"name": name}, doc! {"$set": fields}, None)
+            .await?;
+        Ok(())
+    }
+
+    async fn get_apps(&self, page: &PageRequest) -> StorageResult<Page> {
+        self.get_page(&self.apps, doc! {"published":{"$ne":false}}, page)
+            .await
+    }
+
+    async fn get_apps_by_tags(&self, tags: &TagFilter, page: &PageRequest) -> StorageResult<Page> {
+        self.get_page(&self.apps, tag_filter(tags), page).await
+    }
+
+    async fn get_tag_counts(&self, tags: &TagFilter) -> StorageResult<Vec<Document>> {
+        let cursor = self
+            .apps
+            .aggregate(
+                [
+                    doc! {"$match": tag_filter(tags)},
+                    doc! {"$unwind": "$tags"},
+                    doc! {"$group": {"_id": "$tags", "count": {"$sum": 1}}},
+                    doc! {"$sort": {"count": -1, "_id": 1}},
+                    doc! {"$project": {"_id": 0, "tag": "$_id", "count": 1}},
+                ],
+                None,
+            )
+            .await?;
+        Ok(cursor.try_collect().await?)
+    }
+
+    async fn get_apps_by_author(&self, author: &str, page: &PageRequest) -> StorageResult<Page> {
+        self.get_page(&self.apps, doc! {"author":author}, page)
+            .await
+    }
+
+    async fn replace_app_tag(&self, from: &str, to: &str) -> StorageResult<()> {
+        self.apps
+            .update_many(
+                doc! {"tags": {"$all": [from, to]}},
+                doc! {"$pull": {"tags": from}},
+                None,
+            )
+            .await?;
+        self.apps
+            .update_many(doc! {"tags": from}, doc! {"$set": {"tags.$": to}}, None)
+            .await?;
+        Ok(())
+    }
+
+    async fn find_app(&self, app_id: &str) -> StorageResult<Option<Document>> {
+        Ok(self.apps.find_one(doc! {"app_id":app_id}, None).await?)
+    }
+
+    async fn insert_app(&self, app: Document) -> StorageResult<()> {
+        if self
+            .find_app(app.get_str("app_id").unwrap_or_default())
+            .await?
+            .is_some()
+        {
+            return Err(StorageError::Duplicate);
+        }
+        self.apps.insert_one(app, None).await?;
+        Ok(())
+    }
+
+    async fn update_app(&self, app_id: &str, fields: Document) -> StorageResult<()> {
+        self.apps
+            .update_one(doc! {"app_id": app_id}, doc! {"$set": fields}, None)
+            .await?;
+        Ok(())
+    }
+
+    async fn get_rating_totals(&self) -> StorageResult<(i64, i64)> {
+        let totals = self
+            .rating_totals
+            .find_one(doc! {"_id": RATING_TOTALS}, None)
+            .await?;
+        Ok(match totals {
+            Some(totals) => {
+                let totals = Aggregate::of(&doc! {"rating": totals});
+                (totals.count, totals.sum)
+            }
+            None => (0, 0),
+        })
+    }
+
+    async fn set_ratings(&self, ratings: BTreeMap<String, Document>) -> StorageResult<()> {
+        self.apps
+            .update_many(
+                doc! {},
+                doc! {"$set": {"rating": Aggregate::default().to_document()}},
+                None,
+            )
+            .await?;
+        for (app_id, rating) in ratings {
+            self.apps
+                .update_one(
+                    doc! {"app_id": app_id},
+                    doc! {"$set": {"rating": rating}},
+                    None,
+                )
+                .await?;
+        }
+        self.count_rating_totals().await
+    }
+
+    async fn search_apps(&self, query: &search::Query) -> StorageResult<Vec<Document>> {
+        let options = FindOptions::builder().projection(doc! {"_id" : 0}).build();
+        let cursor = self
+            .apps
+            .find(
+                doc! {"$and": [query.candidate_filter(), {"published":{"$ne":false}}]},
+                options,
+            )
+            .await?;
+        let candidates: Vec<Document> = cursor.try_collect().await?;
+
+        Ok(search::Index::new(&candidates)
+            .search(query)
+            .into_iter()
+            .map(|(i, relevance)| {
+                let mut app = candidates[i].clone();
+                app.insert("relevance", relevance);
+                app
+            })
+            .collect())
+    }
+
+    async fn get_versions(&self, app_id: &str) -> StorageResult<Vec<Document>> {
+        self.get_collection_with_params_and_sort(
+            &self.apps_versions,
+            doc! {"app_id":app_id},
+            doc! {"timestamp": -1},
+        )
+        .await
+    }
+
+    async fn get_versions_of_apps(&self, app_ids: &[String]) -> StorageResult<Vec<Document>> {
+        self.get_collection_with_params_and_sort(
+            &self.apps_versions,
+            doc! {"app_id":{"$in":app_ids}},
+            doc! {"timestamp": -1},
+        )
+        .await
+    }
+
+    async fn insert_version(&self, version: Document) -> StorageResult<()> {
+        self.apps_versions.insert_one(version, None).await?;
+        Ok(())
+    }
+
+    async fn record_download(&self, download: Document) -> StorageResult<bool> {
+        let options = UpdateOptions::builder().upsert(Some(true)).build();
+        let result = self
+            .downloads
+            .update_one(
+                doc! {
+                    "user_name": download.get_str("user_name").unwrap_or_default(),
+                    "app_id": download.get_str("app_id").unwrap_or_default(),
+                    "version": download.get_str("version").unwrap_or_default(),
+                },
+                doc! {"$setOnInsert": download},
+                options,
+            )
+            .await?;
+        Ok(result.upserted_id.is_some())
+    }
+
+    async fn get_download_counts(
+        &self,
+        app_id: &str,
+        day_start: i64,
+        week_start: i64,
+    ) -> StorageResult<Vec<Document>> {
+        let since = |start: i64| doc! {"$sum": {"$cond": [{"$gte": ["$timestamp", start]}, 1, 0]}};
+        let cursor = self
+            .downloads
+            .aggregate(
+                [
+                    doc! {"$match": {"app_id": app_id}},
+                    doc! {
+                        "$group": {
+                            "_id": {"version": "$version", "platform": "$platform"},
+                            "day": since(day_start),
+                            "week": since(week_start),
+                            "all": {"$sum": 1},
+                        },
+                    },
+                    doc! {
+                        "$project": {
+                            "_id": 0,
+                            "version": "$_id.version",
+                            "platform": "$_id.platform",
+                            "day": 1,
+                            "week": 1,
+                            "all": 1,
+                        },
+                    },
+                ],
+                None,
+            )
+            .await?;
+        Ok(cursor.try_collect().await?)
+    }
+
+    async fn get_reviews(&self, filter: &ReviewFilter, page: &PageRequest) -> StorageResult<Page> {
+        let mut params = doc! {"app_id": &filter.app_id, "hidden": {"$ne": true}};
+        let mut score = Document::new();
+        if let Some(min_score) = filter.min_score {
+            score.insert("$gte", min_score);
+        }
+        if let Some(max_score) = filter.max_score {
+            score.insert("$lte", max_score);
+        }
+        if !score.is_empty() {
+            params.insert("score", score);
+        }
+        if filter.with_text {
+            params.insert("text", doc! {"$regex": "\\S"});
+        }
+        if let Some(version) = &filter.version {
+            params.insert("version", version);
+        }
+        self.get_page(&self.reviews, params, page).await
+    }
+
+    async fn get_score_counts(&self) -> StorageResult<Vec<Document>> {
+        let cursor = self
+            .reviews
+            .aggregate(
+                [
+                    doc! {"$match": {"hidden": {"$ne": true}}},
+                    doc! {
+                        "$group": {
+                            "_id": {"app_id": "$app_id", "score": "$score"},
+                            "count": {"$sum": 1},
+                        },
+                    },
+                    doc! {
+                        "$project": {
+                            "_id": 0,
+                            "app_id": "$_id.app_id",
+                            "score": "$_id.score",
+                            "count": 1,
+                        },
+                    },
+                ],
+                None,
+            )
+            .await?;
+        Ok(cursor.try_collect().await?)
+    }
+
+    async fn find_review(&self, user_name: &str, app_id: &str) -> StorageResult<Option<Document>> {
+        Ok(self
+            .reviews
+            .find_one(doc! {"user_name": user_name, "app_id": app_id}, None)
+            .await?)
+    }
+
+    async fn get_reviews_by_user(&self, user_name: &str) -> StorageResult<Vec<Document>> {
+        self.get_collection_with_params_and_sort(
+            &self.reviews,
+            doc! {"user_name": user_name},
+            doc! {"timestamp": -1},
+        )
+        .await
+    }
+
+    async fn upsert_review(
+        &self,
+        user_name: &str,
+        app_id: &str,
+        review: Document,
+    ) -> StorageResult<Option<Document>> {
+        self.change_review(user_name, app_id, ReviewChange::Upsert(review))
+            .await
+    }
+
+    async fn update_review(
+        &self,
+        user_name: &str,
+        app_id: &str,
+        fields: Document,
+    ) -> StorageResult<Option<Document>> {
+        self.change_review(user_name, app_id, ReviewChange::Update(fields))
+            .await
+    }
+
+    async fn delete_review(
+        &self,
+        user_name: &str,
+        app_id: &str,
+    ) -> StorageResult<Option<Document>> {
+        self.change_review(user_name, app_id, ReviewChange::Delete)
+            .await
+    }
+
+    /// The vote and the tallies are changed one after the other, the unique vote
+    /// per user keeps concurrent votes from counting twice
+    async fn vote_review(
+        &self,
+        app_id: &str,
+        author: &str,
+        voter: &str,
+        helpful: Option<bool>,
+    ) -> StorageResult<()> {
+        let filter = doc! {"app_id": app_id, "author": author, "user_name": voter};
+        let previous = match helpful {
+            Some(helpful) => {
+                let options = FindOneAndUpdateOptions::builder()
+                    .upsert(true)
+                    .return_document(ReturnDocument::Before)
+                    .build();
+                self.review_votes
+                    .find_one_and_update(filter, doc! {"$set": {"helpful": helpful}}, options)
+                    .await?
+            }
+            None => self.review_votes.find_one_and_delete(filter, None).await?,
+        };
+
+        let previous = previous.and_then(|vote| vote.get_bool("helpful").ok());
+        if previous != helpful {
+            self.reviews
+                .update_one(
+                    doc! {"app_id": app_id, "user_name": author},
+                    doc! {"$inc": vote_tallies(previous, helpful)},
+                    None,
+                )
+                .await?;
+        }
+        Ok(())
+    }
+
+    async fn insert_review_revision(&self, revision: Document) -> StorageResult<()> {
+        self.review_revisions.insert_one(revision, None).await?;
+        Ok(())
+    }
+
+    async fn insert_review_report(&self, report: Document) -> StorageResult<()> {
+        let options = UpdateOptions::builder().upsert(Some(true)).build();
+        let result = self
+            .review_reports
+            .update_one(
+                doc! {
+                    "app_id": report.get_str("app_id").unwrap_or_default(),
+                    "author": report.get_str("author").unwrap_or_default(),
+                    "user_name": report.get_str("user_name").unwrap_or_default(),
+                    "status": "open",
+                },
+                doc! {"$setOnInsert": report},
+                options,
+            )
+            .await?;
+        match result.upserted_id {
+            Some(_) => Ok(()),
+            None => Err(StorageError::Duplicate),
+        }
+    }
+
+    async fn get_moderation_queue(&self, page: &PageRequest) -> StorageResult<Page> {
+        let groups = vec![
+            doc! {"$match": {"status": "open"}},
+            doc! {"$sort": {"timestamp": 1}},
+            doc! {"$unset": "_id"},
+            doc! {
+                "$group": {
+                    "_id": {"app_id": "$app_id", "user_name": "$author"},
+                    "count": {"$sum": 1},
+                    "since": {"$min": "$timestamp"},
+                    "reports": {"$push": "$$ROOT"},
+                },
+            },
+            doc! {
+                "$project": {
+                    "_id": 0,
+                    "app_id": "$_id.app_id",
+                    "user_name": "$_id.user_name",
+                    "count": 1,
+                    "since": 1,
+                    "reports": 1,
+                },
+            },
+        ];
+        let total = match page.count {
+            true => {
+                let mut pipeline = groups.clone();
+                pipeline.push(doc! {"$count": "total"});
+                let mut cursor = self.review_reports.aggregate(pipeline, None).await?;
+                let total = match cursor.try_next().await? {
+                    Some(count) => count.get_i32("total").unwrap_or_default(),
+                    None => 0,
+                };
+                Some(total as u64)
+            }
+            false => None,
+        };
+
+        let fetch_sort = page.fetch_sort();
+        let mut pipeline = groups;
+        if let Some(cursor) = &page.cursor {
+            pipeline.push(doc! {"$match": keyset_filter(&fetch_sort, cursor)});
+        }
+        let mut sort_params = Document::new();
+        for (field, direction) in &fetch_sort {
+            sort_params.insert(field, direction);
+        }
+        pipeline.push(doc! {"$sort": sort_params});
+        pipeline.push(doc! {"$limit": page.limit as i64 + 1});
+        //only the reviews of the page are joined
+        pipeline.push(doc! {
+            "$lookup": {
+                "from": "reviews",
+                "let": {"app_id": "$app_id", "user_name": "$user_name"},
+                "pipeline": [
+                    {"$match": {"$expr": {"$and": [
+                        {"$eq": ["$app_id", "$$app_id"]},
+                        {"$eq": ["$user_name", "$$user_name"]},
+                    ]}}},
+                    {"$project": {"_id": 0}},
+                ],
+                "as": "review",
+            },
+        });
+        pipeline.push(doc! {
+            "$set": {"review": {"$ifNull": [{"$arrayElemAt": ["$review", 0]}, null]}},
+        });
+
+        let cursor = self.review_reports.aggregate(pipeline, None).await?;
+        Ok(page.page(cursor.try_collect().await?, total))
+    }
+
+    async fn resolve_review_reports(
+        &self,
+        app_id: &str,
+        author: &str,
+        fields: Document,
+    ) -> StorageResult<()> {
+        self.review_reports
+            .update_many(
+                doc! {"app_id": app_id, "author": author, "status": "open"},
+                doc! {"$set": fields},
+                None,
+            )
+            .await?;
+        Ok(())
+    }
+
+    async fn get_revisions_by_user(
+        &self,
+        user_name: &str,
+        since: i64,
+    ) -> StorageResult<Vec<Document>> {
+        self.get_collection_with_params_and_sort(
+            &self.review_revisions,
+            doc! {"user_name": user_name, "timestamp": {"$gte": since}},
+            doc! {"timestamp": -1},
+        )
+        .await
+    }
+
+    async fn get_review_revisions(
+        &self,
+        user_name: &str,
+        app_id: &str,
+    ) -> StorageResult<Vec<Document>> {
+        self.get_collection_with_params_and_sort(
+            &self.review_revisions,
+            doc! {"user_name": user_name, "app_id": app_id},
+            doc! {"replaced": -1},
+        )
+        .await
+    }
+
+    async fn get_tags(&self) -> StorageResult<Vec<Document>> {
+        self.get_collection_with_params_and_sort(&self.tags, doc! {}, doc! {"slug": 1})
+            .await
+    }
+
+    async fn insert_tag(&self, tag: Document) -> StorageResult<()> {
+        if self
+            .tags
+            .find_one(doc! {"slug": tag.get_str("slug").unwrap_or_default()}, None)
+            .await?
+            .is_some()
+        {
+            return Err(StorageError::Duplicate);
+        }
+        self.tags.insert_one(tag, None).await?;
+        Ok(())
+    }
+
+    async fn update_tag(&self, slug: &str, fields: Document) -> StorageResult<()> {
+        self.tags
+            .update_one(doc! {"slug": slug}, doc! {"$set": fields}, None)
+            .await?;
+        Ok(())
+    }
+
+    async fn delete_tag(&self, slug: &str) -> StorageResult<()> {
+        self.tags.delete_one(doc! {"slug": slug}, None).await?;
+        Ok(())
+    }
+
+    async fn find_personal_library(&self, name: &str) -> StorageResult<Option<Document>> {
+        Ok(self
+            .personal_libraries
+            .find_one(doc! {"name":name}, None)
+            .await?)
+    }
+
+    async fn create_personal_library(&self, name: &str) -> StorageResult<()> {
+        self.personal_libraries
+            .insert_one(
+                doc! {
+                    "name":name,
+                    "apps":[]
+                },
+                None,
+            )
+            .await?;
+        Ok(())
+    }
+
+    async fn add_to_personal_library(&self, name: &str, app_id: &str) -> StorageResult<()> {
+        //users without a library get one, only an app already in it is a duplicate
+        let options = UpdateOptions::builder().upsert(Some(true)).build();
+        let result = self
+            .personal_libraries
+            .update_one(
+                doc! {"name": name},
+                doc! {"$addToSet": {
+                    "apps":app_id,
+                }},
+                options,
+            )
+            .await?;
+        if result.upserted_id.is_none() && result.modified_count == 0 {
+            return Err(StorageError::Duplicate);
+        }
+        Ok(())
+    }
+
+    async fn delete_from_personal_library(&self, name: &str, app_id: &str) -> StorageResult<()> {
+        self.personal_libraries
+            .update_one(
+                doc! {"name": name},
+                doc! {"$pull": {
+                    "apps":app_id,
+                }},
+                None,
+            )
+            .await?;
+        Ok(())
+    }
+
+    async fn insert_session(&self, session: Document) -> StorageResult<()> {
+        self.sessions.insert_one(session, None).await?;
+        Ok(())
+    }
+
+    async fn find_session(&self, session_id: &str) -> StorageResult<Option<Document>> {
+        Ok(self
+            .sessions
+            .find_one(doc! {"session_id":session_id}, None)
+            .await?)
+    }
+
+    async fn rotate_session(
+        &self,
+        session_id: &str,
+        refresh_hash: &str,
+        fields: Document,
+    ) -> StorageResult<bool> {
+        let result = self
+            .sessions
+            .update_one(
+                doc! {"session_id": session_id, "refresh_hash": refresh_hash},
+                doc! {"$set": fields},
+                None,
+            )
+            .await?;
+        Ok(result.modified_count == 1)
+    }
+
+    async fn delete_session(&self, session_id: &str) -> StorageResult<()> {
+        self.sessions
+            .delete_one(doc! {"session_id": session_id}, None)
+            .await?;
+        Ok(())
+    }
+
+    async fn delete_user_sessions(&self, name: &str) -> StorageResult<()> {
+        self.sessions.delete_many(doc! {"name": name}, None).await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,154 @@
+// Copyright (c) 2023 artegoser (Artemy Egorov)
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+use bson::Document;
+
+/// Canonical form of a tag: lowercase words joined by `-`, `Text Editor` is `text-editor`
+pub fn slugify(tag: &str) -> String {
+    tag.split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(|word| word.to_lowercase())
+        .collect::<Vec<_>>()
+        .join("-")
+}
+
+/// Catalog of curated tags with their parents and aliases
+pub struct Taxonomy {
+    tags: Vec<Document>,
+}
+
+impl Taxonomy {
+    pub fn new(tags: Vec<Document>) -> Taxonomy {
+        Taxonomy { tags }
+    }
+
+    pub fn find(&self, slug: &str) -> Option<&Document> {
+        self.tags
+            .iter()
+            .find(|tag| tag.get_str("slug").unwrap_or_default() == slug)
+    }
+
+    /// Slug of the tag that uses the name as its slug or alias
+    pub fn owner(&self, name: &str) -> Option<&str> {
+        self.tags
+            .iter()
+            .find(|tag| {
+                tag.get_str("slug").unwrap_or_default() == name || aliases(tag).contains(&name)
+            })
+            .and_then(|tag| tag.get_str("slug").ok())
+    }
+
+    /// Canonical slug of a tag or alias, unknown tags are only slugified
+    pub fn resolve(&self, tag: &str) -> String {
+        let slug = slugify(tag);
+        match self.owner(&slug) {
+            Some(owner) => owner.to_string(),
+            None => slug,
+        }
+    }
+
+    /// Canonical tags without duplicates, keeping their order
+    pub fn normalize(&self, tags: &[String]) -> Vec<String> {
+        let mut normalized: Vec<String> = vec![];
+        for tag in tags.iter().map(|tag| self.resolve(tag)) {
+            if !tag.is_empty() && !normalized.contains(&tag) {
+                normalized.push(tag);
+            }
+        }
+        normalized
+    }
+
+    /// The tag followed by every tag below it
+    pub fn with_children(&self, slug: &str) -> Vec<String> {
+        let mut found = vec![slug.to_string()];
+        let mut i = 0;
+        while i < found.len() {
+            for tag in &self.tags {
+                let child = tag.get_str("slug").unwrap_or_default();
+                if tag.get_str("parent").ok() == Some(found[i].as_str())
+                    && !found.iter().any(|f| f == child)
+                {
+                    found.push(child.to_string());
+                }
+            }
+            i += 1;
+        }
+        found
+    }
+
+    /// Tags whose parent is the given one
+    pub fn children(&self, slug: &str) -> Vec<&str> {
+        self.tags
+            .iter()
+            .filter(|tag| tag.get_str("parent").ok() == Some(slug))
+            .filter_map(|tag| tag.get_str("slug").ok())
+            .collect()
+    }
+}
+
+pub fn aliases(tag: &Document) -> Vec<&str> {
+    match tag.get_array("aliases") {
+        Ok(aliases) => aliases.iter().filter_map(|alias| alias.as_str()).collect(),
+        Err(_) => vec![],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bson::doc;
+
+    fn taxonomy() -> Taxonomy {
+        Taxonomy::new(vec![
+            doc! {"slug": "graphics"},
+            doc! {"slug": "image-editor", "parent": "graphics", "aliases": ["photo-editor"]},
+            doc! {"slug": "raw", "parent": "image-editor"},
+            doc! {"slug": "office"},
+        ])
+    }
+
+    #[test]
+    fn slugifies_tags() {
+        assert_eq!(slugify("Text Editor"), "text-editor");
+        assert_eq!(slugify("  C++ / IDE "), "c-ide");
+        assert_eq!(slugify("--"), "");
+    }
+
+    #[test]
+    fn resolves_aliases() {
+        let taxonomy = taxonomy();
+        assert_eq!(taxonomy.resolve("Photo Editor"), "image-editor");
+        assert_eq!(taxonomy.resolve("Games"), "games");
+        assert_eq!(taxonomy.owner("photo-editor"), Some("image-editor"));
+        assert_eq!(taxonomy.owner("games"), None);
+    }
+
+    #[test]
+    fn normalizes_tag_lists() {
+        let tags = ["photo editor", "Image Editor", "", "office"].map(String::from);
+        assert_eq!(taxonomy().normalize(&tags), ["image-editor", "office"]);
+    }
+
+    #[test]
+    fn finds_children() {
+        let taxonomy = taxonomy();
+        assert_eq!(
+            taxonomy.with_children("graphics"),
+            ["graphics", "image-editor", "raw"]
+        );
+        assert_eq!(taxonomy.children("graphics"), ["image-editor"]);
+        assert!(taxonomy.children("office").is_empty());
+    }
+}
@@ -15,15 +15,6 @@
 
 use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Serialize, Deserialize)]
-pub struct App {
-    id: String,
-    name: String,
-    description: String,
-    version: String,
-    platform: String,
-}
-
 #[derive(Debug, Serialize, Deserialize)]
 pub struct User {
     pub name: String,
@@ -41,6 +32,8 @@ pub struct UserAuth {
 pub struct JwtInfo {
     pub name: String,
     pub role: String,
+    /// Id of the session the token belongs to
+    pub jti: String,
     pub exp: i64,
 }
 
@@ -55,6 +48,8 @@ pub struct ReviewData {
     pub app_id: String,
     pub score: i32,
     pub text: String,
+    /// Version of the app the review is about
+    pub version: Option<String>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -70,5 +65,239 @@ pub struct AppInfo {
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct AppTags {
+    /// Apps with at least one of these tags
+    #[serde(default)]
     pub tags: Vec<String>,
+    /// Apps with every one of these tags
+    #[serde(default)]
+    pub all: Vec<String>,
+    /// Apps with none of these tags
+    #[serde(default)]
+    pub exclude: Vec<String>,
+    /// A tag also matches every tag below it
+    #[serde(default)]
+    pub children: bool,
+}
+
+impl AppTags {
+    pub fn is_empty(&self) -> bool {
+        self.tags.is_empty() && self.all.is_empty() && self.exclude.is_empty()
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RefreshInfo {
+    pub refresh_token: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ConfirmationInfo {
+    pub name: String,
+    pub email: String,
+    /// Changes with every sent mail, only the latest link works
+    pub nonce: String,
+    pub exp: i64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TokenInfo {
+    pub token: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ForgotPasswordInfo {
+    /// User name or email
+    pub login: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ResetPasswordInfo {
+    pub token: String,
+    pub new_password: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct AppData {
+    pub app_id: String,
+    pub name: String,
+    pub description: String,
+    pub icon: String,
+    pub screenshots: Vec<String>,
+    pub repo: String,
+    pub tags: Vec<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct VersionData {
+    pub app_id: String,
+    pub version: String,
+    pub url: String,
+    pub platform: String,
+    /// Empty for builds that run on any architecture
+    #[serde(default)]
+    pub arch: String,
+    #[serde(rename = "type")]
+    pub package_type: String,
+    #[serde(default)]
+    pub changelog: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct VersionsQuery {
+    /// Include pre-release versions like `2.0.0-beta.1`
+    #[serde(default)]
+    pub prerelease: bool,
+    /// Only builds that run on this platform, e.g. `linux` or `windows`
+    pub platform: Option<String>,
+    /// Used with `platform`, e.g. `x86_64` or `arm64`
+    pub arch: Option<String>,
+    /// Preferred package type, e.g. `AppImage` or `msi`
+    #[serde(rename = "type")]
+    pub package_type: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct InstalledApp {
+    pub app_id: String,
+    pub installed_version: String,
+    pub platform: Option<String>,
+    pub arch: Option<String>,
+    #[serde(rename = "type")]
+    pub package_type: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct UpdatesInfo {
+    pub apps: Vec<InstalledApp>,
+    #[serde(default)]
+    pub prerelease: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PageQuery {
+    pub limit: Option<usize>,
+    /// `next` or `prev` of a previous page
+    pub cursor: Option<String>,
+    /// Count all the items of the listing
+    #[serde(default)]
+    pub count: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SearchQuery {
+    pub q: String,
+    /// Only apps with a build for this platform
+    pub platform: Option<String>,
+    /// Used with `platform`
+    pub arch: Option<String>,
+    pub min_rating: Option<f64>,
+    pub limit: Option<usize>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TagData {
+    pub slug: String,
+    pub name: String,
+    pub parent: Option<String>,
+    #[serde(default)]
+    pub aliases: Vec<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TagChange {
+    pub from: String,
+    pub to: String,
+}
+
+#[derive(Debug, Default, Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ReviewSort {
+    #[default]
+    Newest,
+    Oldest,
+    Highest,
+    Lowest,
+    Helpful,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ReviewsQuery {
+    #[serde(default)]
+    pub sort: ReviewSort,
+    pub min_score: Option<i32>,
+    pub max_score: Option<i32>,
+    /// Only reviews that have more than whitespace in `text`
+    #[serde(default)]
+    pub with_text: bool,
+    pub version: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Vote {
+    Helpful,
+    Unhelpful,
+    /// Takes back an earlier vote
+    None,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ReviewVote {
+    pub app_id: String,
+    /// Author of the review
+    pub user_name: String,
+    pub vote: Vote,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ReviewReply {
+    pub app_id: String,
+    /// Author of the review
+    pub user_name: String,
+    pub text: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ReviewAuthor {
+    pub app_id: String,
+    pub user_name: String,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ReportReason {
+    Spam,
+    Abuse,
+    OffTopic,
+    Misleading,
+    Other,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ReviewReport {
+    pub app_id: String,
+    /// Author of the review
+    pub user_name: String,
+    pub reason: ReportReason,
+    pub comment: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ModerationAction {
+    Hide,
+    Restore,
+    Delete,
+    /// Keeps the review and warns its author
+    Warn,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ModerationInfo {
+    pub app_id: String,
+    /// Author of the review
+    pub user_name: String,
+    pub action: ModerationAction,
+    /// Sent to the author with a warning
+    pub note: Option<String>,
 }
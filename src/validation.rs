@@ -0,0 +1,500 @@
+// Copyright (c) 2023 artegoser (Artemy Egorov)
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+use serde::Serialize;
+
+use crate::errors::{CoreError, CoreResult};
+use crate::pagination::MAX_PAGE_SIZE;
+use crate::taxonomy;
+use crate::types::*;
+
+pub const MAX_REVIEW_CHARS: usize = 2000;
+pub const MAX_REPLY_CHARS: usize = 2000;
+pub const MAX_REPORT_COMMENT_CHARS: usize = 500;
+pub const MAX_UPDATE_CHECK_APPS: usize = 500;
+const MAX_PASSWORD_CHARS: usize = 128;
+const MAX_SEARCH_CHARS: usize = 200;
+const IMAGE_EXTENSIONS: [&str; 6] = ["png", "jpg", "jpeg", "gif", "webp", "svg"];
+
+#[derive(Debug, Serialize)]
+pub struct FieldError {
+    /// Path of the field in the request body, e.g. `screenshots[1]`
+    pub field: String,
+    pub msg: String,
+}
+
+/// Collects every failed field instead of stopping at the first one
+#[derive(Default)]
+pub struct Validator {
+    errors: Vec<FieldError>,
+}
+
+impl Validator {
+    fn fail(&mut self, field: &str, msg: String) {
+        self.errors.push(FieldError {
+            field: field.to_string(),
+            msg,
+        });
+    }
+
+    /// Characters, not bytes
+    pub fn length(&mut self, field: &str, value: &str, min: usize, max: usize) -> &mut Self {
+        let length = value.chars().count();
+        if length < min || length > max {
+            self.fail(field, format!("Must be from {} to {} characters", min, max));
+        }
+        self
+    }
+
+    /// Same as `length` but whitespace around the text does not count
+    pub fn text(&mut self, field: &str, value: &str, min: usize, max: usize) -> &mut Self {
+        self.length(field, value.trim(), min, max)
+    }
+
+    pub fn range(&mut self, field: &str, value: i32, min: i32, max: i32) -> &mut Self {
+        if !(min..=max).contains(&value) {
+            self.fail(field, format!("Must be from {} to {}", min, max));
+        }
+        self
+    }
+
+    pub fn score(&mut self, field: &str, value: f64, min: f64, max: f64) -> &mut Self {
+        if !(min..=max).contains(&value) {
+            self.fail(field, format!("Must be from {} to {}", min, max));
+        }
+        self
+    }
+
+    pub fn count(&mut self, field: &str, value: usize, min: usize, max: usize) -> &mut Self {
+        if !(min..=max).contains(&value) {
+            self.fail(field, format!("Must have from {} to {} items", min, max));
+        }
+        self
+    }
+
+    pub fn password(&mut self, field: &str, value: &str) -> &mut Self {
+        self.length(field, value, 1, MAX_PASSWORD_CHARS)
+    }
+
+    pub fn app_id(&mut self, field: &str, value: &str) -> &mut Self {
+        self.length(field, value, 3, 5)
+    }
+
+    /// 3 to 16 latin letters, digits, `_` or `-`, only new names are checked for the charset
+    pub fn user_name(&mut self, field: &str, value: &str) -> &mut Self {
+        self.length(field, value, 3, 16);
+        if !value
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+        {
+            self.fail(
+                field,
+                "Only latin letters, digits, _ and - are allowed".to_string(),
+            );
+        }
+        self
+    }
+
+    pub fn email(&mut self, field: &str, value: &str) -> &mut Self {
+        let valid = match value.split_once('@') {
+            Some((local, domain)) => {
+                !local.is_empty()
+                    && value.len() <= 254
+                    && !domain.contains('@')
+                    && domain.contains('.')
+                    && !domain.starts_with('.')
+                    && !domain.ends_with('.')
+                    && !value.chars().any(char::is_whitespace)
+            }
+            None => false,
+        };
+        if !valid {
+            self.fail(field, "Must be an email address".to_string());
+        }
+        self
+    }
+
+    /// `http` or `https` url with a host
+    pub fn url(&mut self, field: &str, value: &str) -> &mut Self {
+        if host(value).is_none() {
+            self.fail(field, "Must be an http or https url".to_string());
+        }
+        self
+    }
+
+    /// Url of a png, jpg, gif, webp or svg file
+    pub fn image_url(&mut self, field: &str, value: &str) -> &mut Self {
+        if host(value).is_none() {
+            self.fail(field, "Must be an http or https url".to_string());
+        } else if !is_image(value) {
+            self.fail(
+                field,
+                format!("Must link to a {} image", IMAGE_EXTENSIONS.join(", ")),
+            );
+        }
+        self
+    }
+
+    pub fn finish(self) -> CoreResult<()> {
+        match self.errors.is_empty() {
+            true => Ok(()),
+            false => Err(CoreError::Fields(self.errors)),
+        }
+    }
+}
+
+fn host(url: &str) -> Option<&str> {
+    let rest = url
+        .strip_prefix("https://")
+        .or_else(|| url.strip_prefix("http://"))?;
+    let host = rest.split(['/', '?', '#']).next().unwrap_or_default();
+    match host.is_empty() || url.chars().any(char::is_whitespace) {
+        true => None,
+        false => Some(host),
+    }
+}
+
+fn is_image(url: &str) -> bool {
+    let path = url.split(['?', '#']).next().unwrap_or_default();
+    match path.rsplit_once('.') {
+        Some((_, extension)) => IMAGE_EXTENSIONS.contains(&extension.to_lowercase().as_str()),
+        None => false,
+    }
+}
+
+/// Rules of a request body, checked before anything is read or written
+pub trait Validate {
+    fn rules(&self, v: &mut Validator);
+
+    fn validate(&self) -> CoreResult<()> {
+        let mut v = Validator::default();
+        self.rules(&mut v);
+        v.finish()
+    }
+}
+
+impl Validate for User {
+    fn rules(&self, v: &mut Validator) {
+        v.user_name("name", &self.name)
+            .password("password", &self.password)
+            .email("email", &self.email);
+    }
+}
+
+impl Validate for UserData {
+    fn rules(&self, v: &mut Validator) {
+        v.email("email", &self.email);
+        if !self.img.is_empty() {
+            v.image_url("img", &self.img);
+        }
+    }
+}
+
+impl Validate for ReviewData {
+    fn rules(&self, v: &mut Validator) {
+        v.app_id("app_id", &self.app_id)
+            .range("score", self.score, 1, 10)
+            .text("text", &self.text, 2, MAX_REVIEW_CHARS);
+        if let Some(version) = &self.version {
+            v.length("version", version, 1, 64);
+        }
+    }
+}
+
+impl Validate for AppData {
+    fn rules(&self, v: &mut Validator) {
+        v.app_id("app_id", &self.app_id)
+            .text("name", &self.name, 1, 16)
+            .image_url("icon", &self.icon)
+            .url("repo", &self.repo);
+        for (i, screenshot) in self.screenshots.iter().enumerate() {
+            v.image_url(&format!("screenshots[{}]", i), screenshot);
+        }
+        for (i, tag) in self.tags.iter().enumerate() {
+            v.text(&format!("tags[{}]", i), tag, 1, 32);
+        }
+    }
+}
+
+impl Validate for VersionData {
+    fn rules(&self, v: &mut Validator) {
+        v.app_id("app_id", &self.app_id)
+            .length("version", &self.version, 1, 64)
+            .url("url", &self.url)
+            .length("platform", &self.platform, 1, 32)
+            .length("type", &self.package_type, 1, 32);
+    }
+}
+
+impl Validate for TagData {
+    fn rules(&self, v: &mut Validator) {
+        if self.slug.is_empty() || taxonomy::slugify(&self.slug) != self.slug {
+            v.fail("slug", "Must be lowercase words joined by -".to_string());
+        }
+        v.text("name", &self.name, 1, 32);
+    }
+}
+
+impl Validate for ReviewVote {
+    fn rules(&self, v: &mut Validator) {
+        v.app_id("app_id", &self.app_id)
+            .length("user_name", &self.user_name, 3, 16);
+    }
+}
+
+impl Validate for ReviewAuthor {
+    fn rules(&self, v: &mut Validator) {
+        v.app_id("app_id", &self.app_id)
+            .length("user_name", &self.user_name, 3, 16);
+    }
+}
+
+impl Validate for ReviewReply {
+    fn rules(&self, v: &mut Validator) {
+        v.app_id("app_id", &self.app_id)
+            .length("user_name", &self.user_name, 3, 16)
+            .text("text", &self.text, 1, MAX_REPLY_CHARS);
+    }
+}
+
+impl Validate for ReviewReport {
+    fn rules(&self, v: &mut Validator) {
+        v.app_id("app_id", &self.app_id)
+            .length("user_name", &self.user_name, 3, 16);
+        if let Some(comment) = &self.comment {
+            v.length("comment", comment, 0, MAX_REPORT_COMMENT_CHARS);
+        }
+    }
+}
+
+impl Validate for ModerationInfo {
+    fn rules(&self, v: &mut Validator) {
+        v.app_id("app_id", &self.app_id)
+            .length("user_name", &self.user_name, 3, 16);
+    }
+}
+
+impl Validate for RefreshInfo {
+    fn rules(&self, v: &mut Validator) {
+        v.length("refresh_token", &self.refresh_token, 1, 256);
+    }
+}
+
+impl Validate for TokenInfo {
+    fn rules(&self, v: &mut Validator) {
+        v.length("token", &self.token, 1, 2048);
+    }
+}
+
+impl Validate for ReviewsQuery {
+    fn rules(&self, v: &mut Validator) {
+        if let Some(min_score) = self.min_score {
+            v.range("min_score", min_score, 1, 10);
+        }
+        if let Some(max_score) = self.max_score {
+            v.range("max_score", max_score, 1, 10);
+        }
+    }
+}
+
+impl Validate for UserAuth {
+    fn rules(&self, v: &mut Validator) {
+        v.length("name", &self.name, 3, 16)
+            .password("password", &self.password);
+    }
+}
+
+impl Validate for PasswordsInf {
+    fn rules(&self, v: &mut Validator) {
+        v.password("old_password", &self.old_password)
+            .password("new_password", &self.new_password);
+    }
+}
+
+impl Validate for ForgotPasswordInfo {
+    fn rules(&self, v: &mut Validator) {
+        v.length("login", &self.login, 3, 254);
+    }
+}
+
+impl Validate for ResetPasswordInfo {
+    fn rules(&self, v: &mut Validator) {
+        v.length("token", &self.token, 1, 128)
+            .password("new_password", &self.new_password);
+    }
+}
+
+impl Validate for TagChange {
+    fn rules(&self, v: &mut Validator) {
+        v.text("from", &self.from, 1, 32)
+            .text("to", &self.to, 1, 32);
+    }
+}
+
+impl Validate for UpdatesInfo {
+    fn rules(&self, v: &mut Validator) {
+        v.count("apps", self.apps.len(), 0, MAX_UPDATE_CHECK_APPS);
+        for (i, app) in self.apps.iter().enumerate() {
+            v.app_id(&format!("apps[{}].app_id", i), &app.app_id)
+                .length(
+                    &format!("apps[{}].installed_version", i),
+                    &app.installed_version,
+                    1,
+                    64,
+                );
+        }
+    }
+}
+
+impl Validate for SearchQuery {
+    fn rules(&self, v: &mut Validator) {
+        v.text("q", &self.q, 1, MAX_SEARCH_CHARS);
+        if let Some(min_rating) = self.min_rating {
+            v.score("min_rating", min_rating, 1.0, 10.0);
+        }
+        if let Some(limit) = self.limit {
+            v.count("limit", limit, 1, MAX_PAGE_SIZE);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::de::DeserializeOwned;
+    use serde_json::json;
+
+    /// Fields that failed validation of the json body
+    fn failed<T: Validate + DeserializeOwned>(body: serde_json::Value) -> Vec<String> {
+        let request: T = serde_json::from_value(body).unwrap();
+        match request.validate() {
+            Ok(()) => vec![],
+            Err(CoreError::Fields(errors)) => errors.into_iter().map(|e| e.field).collect(),
+            Err(_) => panic!("validation failed without field errors"),
+        }
+    }
+
+    #[test]
+    fn checks_new_users() {
+        let valid = json!({"name": "alice_1", "password": "secret", "email": "a@b.org"});
+        assert!(failed::<User>(valid).is_empty());
+        let invalid = json!({"name": "al ice", "password": "", "email": "a@b"});
+        assert_eq!(failed::<User>(invalid), ["name", "password", "email"]);
+    }
+
+    #[test]
+    fn checks_emails() {
+        let mut v = Validator::default();
+        v.email("a", "user@mail.example.org")
+            .email("b", "@example.org")
+            .email("c", "user@.org")
+            .email("d", "us er@example.org")
+            .email("e", "user@example.org.");
+        let fields: Vec<String> = v.errors.into_iter().map(|e| e.field).collect();
+        assert_eq!(fields, ["b", "c", "d", "e"]);
+    }
+
+    #[test]
+    fn checks_urls() {
+        assert_eq!(host("https://example.org/a?b"), Some("example.org"));
+        assert_eq!(host("ftp://example.org"), None);
+        assert_eq!(host("https:///path"), None);
+        assert!(is_image("https://example.org/icon.PNG?size=2"));
+        assert!(!is_image("https://example.org/icon.exe"));
+    }
+
+    #[test]
+    fn collects_every_field() {
+        let app = json!({
+            "app_id": "abc",
+            "name": "  ",
+            "description": "",
+            "icon": "https://example.org/icon.txt",
+            "repo": "example.org",
+            "screenshots": ["https://example.org/1.png", "https://example.org/2"],
+            "tags": ["editor", ""],
+        });
+        assert_eq!(
+            failed::<AppData>(app),
+            ["name", "icon", "repo", "screenshots[1]", "tags[1]"]
+        );
+    }
+
+    #[test]
+    fn checks_reviews() {
+        let review = json!({"app_id": "abc", "score": 11, "text": " a ", "version": ""});
+        assert_eq!(failed::<ReviewData>(review), ["score", "text", "version"]);
+        let long = json!({"app_id": "abc", "score": 5, "text": "ä".repeat(MAX_REVIEW_CHARS)});
+        assert!(failed::<ReviewData>(long).is_empty());
+    }
+
+    #[test]
+    fn checks_tags() {
+        let tag = json!({"slug": "Text Editor", "name": "Text editor"});
+        assert_eq!(failed::<TagData>(tag), ["slug"]);
+        let tag = json!({"slug": "text-editor", "name": "Text editor"});
+        assert!(failed::<TagData>(tag).is_empty());
+    }
+
+    #[test]
+    fn checks_update_checks() {
+        let app = json!({"app_id": "abc", "installed_version": "1.0"});
+        let body = json!({"apps": vec![app.clone(); MAX_UPDATE_CHECK_APPS]});
+        assert!(failed::<UpdatesInfo>(body).is_empty());
+        let body = json!({"apps": vec![app; MAX_UPDATE_CHECK_APPS + 1]});
+        assert_eq!(failed::<UpdatesInfo>(body), ["apps"]);
+        let body = json!({"apps": [{"app_id": "a", "installed_version": ""}]});
+        assert_eq!(
+            failed::<UpdatesInfo>(body),
+            ["apps[0].app_id", "apps[0].installed_version"]
+        );
+    }
+
+    #[test]
+    fn checks_searches() {
+        let search = json!({"q": "editor", "min_rating": 7.5, "limit": MAX_PAGE_SIZE});
+        assert!(failed::<SearchQuery>(search).is_empty());
+        let search = json!({"q": " ", "min_rating": 0.5, "limit": 0});
+        assert_eq!(failed::<SearchQuery>(search), ["q", "min_rating", "limit"]);
+    }
+
+    #[test]
+    fn checks_passwords() {
+        let change =
+            json!({"old_password": "old", "new_password": "x".repeat(MAX_PASSWORD_CHARS + 1)});
+        assert_eq!(failed::<PasswordsInf>(change), ["new_password"]);
+        let reset = json!({"token": "", "new_password": "new"});
+        assert_eq!(failed::<ResetPasswordInfo>(reset), ["token"]);
+    }
+
+    #[test]
+    fn checks_tokens() {
+        assert_eq!(
+            failed::<RefreshInfo>(json!({"refresh_token": ""})),
+            ["refresh_token"]
+        );
+        assert!(failed::<RefreshInfo>(json!({"refresh_token": "abc"})).is_empty());
+        assert_eq!(failed::<TokenInfo>(json!({"token": ""})), ["token"]);
+        assert!(failed::<TokenInfo>(json!({"token": "abc"})).is_empty());
+    }
+
+    #[test]
+    fn checks_review_scores() {
+        assert!(failed::<ReviewsQuery>(json!({})).is_empty());
+        assert!(failed::<ReviewsQuery>(json!({"min_score": 1, "max_score": 10})).is_empty());
+        let query = json!({"min_score": 0, "max_score": 11});
+        assert_eq!(failed::<ReviewsQuery>(query), ["min_score", "max_score"]);
+    }
+}
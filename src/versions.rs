@@ -0,0 +1,269 @@
+// Copyright (c) 2023 artegoser (Artemy Egorov)
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+use bson::{Bson, Document};
+use semver::Version;
+use std::cmp::Ordering;
+
+/// Parses a version, tolerating a `v` prefix and missing minor or patch numbers (`v1.2` is `1.2.0`)
+pub fn parse(version: &str) -> Option<Version> {
+    let version = version.trim();
+    let version = version.strip_prefix(['v', 'V']).unwrap_or(version);
+    if let Ok(parsed) = Version::parse(version) {
+        return Some(parsed);
+    }
+
+    let (core, rest) = match version.find(['-', '+']) {
+        Some(i) => version.split_at(i),
+        None => (version, ""),
+    };
+    let mut parts: Vec<&str> = core.split('.').collect();
+    if parts.is_empty() || parts.len() > 3 || parts.iter().any(|p| p.parse::<u64>().is_err()) {
+        return None;
+    }
+    while parts.len() < 3 {
+        parts.push("0");
+    }
+    Version::parse(&format!("{}{}", parts.join("."), rest)).ok()
+}
+
+pub fn is_prerelease(version: &Document) -> bool {
+    match parse(version.get_str("version").unwrap_or_default()) {
+        Some(parsed) => !parsed.pre.is_empty(),
+        None => false,
+    }
+}
+
+/// Newest first: higher versions by semver precedence, then versions that
+/// can not be parsed, each group falling back to the release timestamp
+pub fn newest_first(a: &Document, b: &Document) -> Ordering {
+    let parsed_a = parse(a.get_str("version").unwrap_or_default());
+    let parsed_b = parse(b.get_str("version").unwrap_or_default());
+    let by_version = match (parsed_a, parsed_b) {
+        (Some(a), Some(b)) => b.cmp_precedence(&a),
+        (Some(_), None) => Ordering::Less,
+        (None, Some(_)) => Ordering::Greater,
+        (None, None) => Ordering::Equal,
+    };
+    by_version.then_with(|| timestamp(b).total_cmp(&timestamp(a)))
+}
+
+/// Sorts versions newest first, dropping pre-releases unless asked for
+pub fn sort(mut versions: Vec<Document>, prerelease: bool) -> Vec<Document> {
+    if !prerelease {
+        versions.retain(|v| !is_prerelease(v));
+    }
+    versions.sort_by(newest_first);
+    versions
+}
+
+/// Whether `latest` is a newer release than `installed`, versions that can
+/// not be compared are newer whenever they differ
+pub fn is_newer(latest: &str, installed: &str) -> bool {
+    match (parse(latest), parse(installed)) {
+        (Some(latest), Some(installed)) => latest.cmp_precedence(&installed) == Ordering::Greater,
+        _ => latest.trim() != installed.trim(),
+    }
+}
+
+/// The first `max_chars` characters of a changelog
+pub fn excerpt(changelog: &str, max_chars: usize) -> String {
+    match changelog.char_indices().nth(max_chars) {
+        Some((i, _)) => format!("{}…", changelog[..i].trim_end()),
+        None => changelog.to_string(),
+    }
+}
+
+fn timestamp(version: &Document) -> f64 {
+    match version.get("timestamp") {
+        Some(Bson::Int32(n)) => *n as f64,
+        Some(Bson::Int64(n)) => *n as f64,
+        Some(Bson::Double(n)) => *n,
+        _ => 0.0,
+    }
+}
+
+/// Platform, architecture and package type a client wants a build for
+pub struct Target {
+    platform: String,
+    arch: Option<String>,
+    package_type: Option<String>,
+}
+
+impl Target {
+    pub fn new(platform: &str, arch: Option<&str>, package_type: Option<&str>) -> Target {
+        Target {
+            platform: normalize_platform(platform),
+            arch: arch.map(normalize_arch),
+            package_type: package_type.map(|t| t.trim().to_lowercase()),
+        }
+    }
+
+    /// How well a build fits, `None` if it can not run on the target.
+    ///
+    /// The platform and architecture must match or the build must be universal
+    /// (`any`, or without an architecture), the package type is only a preference.
+    pub fn score(&self, build: &Document) -> Option<u32> {
+        let mut score = 0;
+
+        match normalize_platform(build.get_str("platform").unwrap_or_default()).as_str() {
+            platform if platform == self.platform => score += 4,
+            "any" => {}
+            _ => return None,
+        }
+
+        let build_arch = normalize_arch(build.get_str("arch").unwrap_or_default());
+        match &self.arch {
+            Some(arch) if *arch == build_arch => score += 2,
+            _ if build_arch == "any" => {}
+            Some(_) => return None,
+            None => {}
+        }
+
+        if let Some(package_type) = &self.package_type {
+            if build.get_str("type").unwrap_or_default().to_lowercase() == *package_type {
+                score += 1;
+            }
+        }
+
+        Some(score)
+    }
+
+    /// Builds that can run on the target, keeping their order
+    pub fn filter(&self, versions: Vec<Document>) -> Vec<Document> {
+        versions
+            .into_iter()
+            .filter(|v| self.score(v).is_some())
+            .collect()
+    }
+
+    /// The best fitting build of the newest version that has one
+    pub fn pick(&self, versions: Vec<Document>) -> Option<Document> {
+        let versions = self.filter(versions);
+        let newest = versions
+            .first()?
+            .get_str("version")
+            .unwrap_or_default()
+            .to_string();
+        versions
+            .into_iter()
+            .take_while(|v| v.get_str("version").unwrap_or_default() == newest)
+            .enumerate()
+            .max_by_key(|(i, v)| (self.score(v), std::cmp::Reverse(*i)))
+            .map(|(_, v)| v)
+    }
+}
+
+fn normalize_platform(platform: &str) -> String {
+    let platform = platform.trim().to_lowercase();
+    match platform.as_str() {
+        "" | "all" | "universal" => "any".to_string(),
+        "win" | "win32" | "win64" => "windows".to_string(),
+        "mac" | "osx" | "darwin" => "macos".to_string(),
+        _ => platform,
+    }
+}
+
+fn normalize_arch(arch: &str) -> String {
+    let arch = arch.trim().to_lowercase();
+    match arch.as_str() {
+        "" | "all" | "universal" | "noarch" => "any".to_string(),
+        "amd64" | "x64" | "x86-64" => "x86_64".to_string(),
+        "aarch64" | "armv8" => "arm64".to_string(),
+        "i386" | "i686" | "ia32" => "x86".to_string(),
+        _ => arch,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bson::doc;
+
+    #[test]
+    fn parses_loose_versions() {
+        assert_eq!(parse("v1.2"), Some(Version::new(1, 2, 0)));
+        assert_eq!(parse(" 3 "), Some(Version::new(3, 0, 0)));
+        assert_eq!(parse("1.2-beta").unwrap().pre.as_str(), "beta");
+        assert_eq!(parse("1.2.3.4"), None);
+        assert_eq!(parse("latest"), None);
+    }
+
+    #[test]
+    fn sorts_newest_first_without_prereleases() {
+        let versions = vec![
+            doc! {"version": "1.9.0", "timestamp": 3.0},
+            doc! {"version": "nightly", "timestamp": 5.0},
+            doc! {"version": "1.10.0", "timestamp": 1.0},
+            doc! {"version": "2.0.0-rc.1", "timestamp": 4.0},
+        ];
+        let names = |versions: Vec<Document>| -> Vec<String> {
+            versions
+                .iter()
+                .map(|v| v.get_str("version").unwrap().to_string())
+                .collect()
+        };
+        assert_eq!(
+            names(sort(versions.clone(), false)),
+            ["1.10.0", "1.9.0", "nightly"]
+        );
+        assert_eq!(
+            names(sort(versions, true)),
+            ["2.0.0-rc.1", "1.10.0", "1.9.0", "nightly"]
+        );
+    }
+
+    #[test]
+    fn picks_the_best_build_of_the_newest_version() {
+        let versions = vec![
+            doc! {"version": "2.0.0", "platform": "linux", "arch": "x86_64", "type": "deb"},
+            doc! {"version": "2.0.0", "platform": "any"},
+            doc! {"version": "2.0.0", "platform": "Linux", "arch": "amd64", "type": "AppImage"},
+            doc! {"version": "1.0.0", "platform": "win", "arch": "x64"},
+        ];
+        let linux = Target::new("linux", Some("x64"), Some("appimage"));
+        assert_eq!(
+            linux.pick(versions.clone()).unwrap().get_str("type"),
+            Ok("AppImage")
+        );
+        let arm = Target::new("linux", Some("aarch64"), None);
+        assert_eq!(
+            arm.pick(versions.clone()).unwrap().get_str("platform"),
+            Ok("any")
+        );
+        let windows = Target::new("win64", Some("x86_64"), None);
+        assert_eq!(windows.filter(versions.clone()).len(), 2);
+        assert_eq!(
+            windows.pick(versions).unwrap().get_str("platform"),
+            Ok("any")
+        );
+    }
+
+    #[test]
+    fn compares_installed_versions() {
+        assert!(is_newer("1.10", "1.9.9"));
+        assert!(!is_newer("v1.0.0", "1"));
+        assert!(!is_newer("1.0.0-beta", "1.0.0"));
+        assert!(is_newer("nightly-2", "nightly-1"));
+        assert!(!is_newer("nightly", " nightly"));
+    }
+
+    #[test]
+    fn cuts_changelogs() {
+        assert_eq!(excerpt("short", 10), "short");
+        assert_eq!(excerpt("Fixes crash on start", 6), "Fixes…");
+        assert_eq!(excerpt("ääää", 2), "ää…");
+    }
+}